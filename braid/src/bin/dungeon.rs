@@ -0,0 +1,17 @@
+use braid::dungeon::generator::Algorithm;
+use braid::dungeon::server::{self, Game};
+use braid::dungeon::visibility::Visibility;
+
+#[tokio::main]
+async fn main() {
+    let game = match Game::new(10, 10, 3, Algorithm::default(), Visibility::default(), 100, 1000.0) { // Initialize the game with a 10x10 maze of 3 floors, line of sight, 100 max turns, and initial treasure of 1000.
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("Could not set up the game: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = server::serve(game, "127.0.0.1:7878").await { // Serve it on localhost port 7878.
+        eprintln!("Server failed: {}", e);
+    }
+}
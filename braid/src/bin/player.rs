@@ -0,0 +1,22 @@
+use braid::player::client::{GameOutcome, Player};
+
+fn main() {
+    let mut player = Player::new(1, 10, 10, 3); // Create a new player with ID 1 and a 10x10 maze of 3 floors.
+    let mut stream = player.connect("127.0.0.1:7878"); // Connect to the server.
+
+    // Simulate exploration for the demo.
+    player.simulate_exploration(&mut stream, 20);
+
+    // A dungeon whose reveal does not match what it committed to has cheated.
+    match player.outcome() {
+        Some(GameOutcome::BadSolution) => {
+            eprintln!("The dungeon's solution path does not open its commitment");
+            std::process::exit(1);
+        }
+        Some(GameOutcome::BadLayout) => {
+            eprintln!("The dungeon's maze does not open its layout commitment");
+            std::process::exit(1);
+        }
+        Some(GameOutcome::Finished { .. }) | None => {}
+    }
+}
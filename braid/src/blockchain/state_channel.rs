@@ -1,9 +1,8 @@
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use secp256k1::{Message, Secp256k1, SecretKey, Signature};
-use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct State {
     pub player_address: String,
     pub move_hash: Vec<u8>,
@@ -44,8 +43,7 @@ impl StateChannel {
         let state_bytes = bincode::serialize(&self.current_state).unwrap();
         let state_hash = Sha256::digest(&state_bytes);
         let message = Message::from_slice(&state_hash).unwrap();
-        let sig = secp.sign(&message, secret_key);
-        sig
+        secp.sign(&message, secret_key)
     }
 
     // Update the state with a new move.
@@ -79,21 +77,31 @@ impl StateChannel {
     }
 }
 
-// Example of using secp256k1 for signing and verifying.
-fn example_usage() {
-    let secp = Secp256k1::new();
-    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut channel = StateChannel::new("player_address", "server_address");
-    let move_hash = vec![0, 1, 2, 3];
-    let turn_number = 1;
+    // Example of using secp256k1 for signing and verifying.
+    #[test]
+    fn test_example_usage() {
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut secp256k1::rand::thread_rng());
+
+        let mut channel = StateChannel::new("player_address", "server_address");
+        let move_hash = vec![0, 1, 2, 3];
+        let turn_number = 1;
 
-    // Player signs the state.
-    let player_sig = channel.sign_state(&secret_key);
+        // Player signs the state.
+        let player_sig = channel.sign_state(&secret_key);
+        assert!(channel.verify_state(&channel.current_state, &player_sig, &public_key));
 
-    // Server updates the state and signs it.
-    channel.update_state(move_hash.clone(), turn_number, player_sig.clone(), player_sig.clone());
+        // Server updates the state and signs it.
+        channel.update_state(move_hash, turn_number, player_sig, player_sig);
 
-    // Verify the state.
-    assert!(channel.verify_state(&channel.current_state, &player_sig, &public_key));
+        // Verify the state: the old signature no longer covers it, a new one does.
+        assert!(!channel.verify_state(&channel.current_state, &player_sig, &public_key));
+        let new_sig = channel.sign_state(&secret_key);
+        assert!(channel.verify_state(&channel.current_state, &new_sig, &public_key));
+        assert_eq!(StateChannel::deserialize_state(&channel.serialize_state()), channel.current_state);
+    }
 }
@@ -1,9 +1,8 @@
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use secp256k1::{Message, Secp256k1, SecretKey, Signature};
-use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct State {
     pub player_address: String,
     pub move_hash: Vec<u8>,
@@ -44,8 +43,7 @@ impl StateChannel {
         let state_bytes = bincode::serialize(&self.current_state).unwrap();
         let state_hash = Sha256::digest(&state_bytes);
         let message = Message::from_slice(&state_hash).unwrap();
-        let sig = secp.sign(&message, secret_key);
-        sig
+        secp.sign(&message, secret_key)
     }
 
     // Update the state with a new move.
@@ -80,6 +78,7 @@ impl StateChannel {
 }
 
 // Structure representing a blockchain transaction.
+#[derive(Serialize, Deserialize)]
 pub struct BlockchainTransaction {
     pub sender: String,
     pub receiver: String,
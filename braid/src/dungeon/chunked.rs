@@ -0,0 +1,283 @@
+use std::collections::{HashMap, VecDeque};
+use rand::{Rng, RngCore, SeedableRng};
+use crate::dungeon::generator::Algorithm;
+use crate::dungeon::maze::{Cell, Maze, MazeRng};
+use crate::dungeon::commitment::{self, Commitment, Domain, Opening};
+use sha2::{Sha256, Digest};
+
+/*
+ * - Chunked Maze: A square-tiled maze too large to hold in memory at once, split into square chunks
+ *   of chunk_size cells. Only the chunks in use are kept, up to max_chunks of them.
+ * - Chunk Generation: Each chunk is an ordinary Maze generated from a seed derived from the
+ *   maze's seed and the chunk's position, so any chunk can be rebuilt on demand, in any order,
+ *   by the dungeon or by a verifier.
+ * - Seams: The walls between neighboring chunks stay closed except for openings whose positions
+ *   are derived from the seed and the seam alone, so both chunks open the same cells without
+ *   generating each other. Every chunk is connected inside and every seam has an opening, so the
+ *   whole maze is connected.
+ * - Global Queries: Cells, walls and open neighbors addressed by coordinates in the whole maze.
+ * - Masked View: Views are served per chunk from a mask over the chunk's cells, together with
+ *   the chunk's origin in the whole maze.
+ * - Commitments: Each chunk is committed to separately, so a chunk can be checked without the rest.
+ *
+ * A chunk's own starts and treasure come from its generation and mean nothing in the whole maze.
+ */
+
+// Representation of a large maze generated chunk by chunk.
+pub struct ChunkedMaze {
+    pub width: usize,           // Width of the whole maze.
+    pub height: usize,          // Height of the whole maze.
+    pub chunk_size: usize,      // Width and height of a chunk. Chunks on the east and south edges may be smaller.
+    pub seed: u64,              // Seed every chunk and seam is derived from.
+    pub algorithm: Algorithm,   // Algorithm used to carve each chunk.
+    pub braid: f64,             // Fraction of dead ends removed inside each chunk.
+    pub rough: f64,             // Fraction of cells covered in rough terrain.
+    pub seam_openings: usize,   // Openings in each seam between two chunks, at least one.
+    pub max_chunks: usize,      // Most chunks kept generated at once.
+    pub starts: Vec<(usize, usize)>, // Start cells in the whole maze.
+    pub treasure: (usize, usize), // Cell holding the treasure, at the center of the whole maze.
+    chunks: HashMap<(usize, usize), Maze>, // Generated chunks by chunk position.
+    generated: VecDeque<(usize, usize)>, // Generated chunks, oldest first, for eviction.
+}
+
+// The side of a chunk a seam lies along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Seam {
+    East,  // Between chunk (cx, cy) and chunk (cx + 1, cy).
+    South, // Between chunk (cx, cy) and chunk (cx, cy + 1).
+}
+
+impl ChunkedMaze {
+    pub fn new(width: usize, height: usize, seed: u64) -> Self {
+        ChunkedMaze {
+            width,
+            height,
+            chunk_size: 64,
+            seed,
+            algorithm: Algorithm::default(),
+            braid: 0.0,
+            rough: 0.0,
+            seam_openings: 1,
+            max_chunks: 64,
+            starts: vec![(0, 0)],
+            treasure: (width / 2, height / 2),
+            chunks: HashMap::new(),
+            generated: VecDeque::new(),
+        }
+    }
+
+    // Number of chunks across and down.
+    pub fn chunks_across(&self) -> (usize, usize) {
+        (self.width.div_ceil(self.chunk_size), self.height.div_ceil(self.chunk_size))
+    }
+
+    // Get the chunk holding a cell, and the cell's position within it.
+    pub fn locate(&self, x: usize, y: usize) -> ((usize, usize), (usize, usize)) {
+        let size = self.chunk_size;
+        ((x / size, y / size), (x % size, y % size))
+    }
+
+    // Get the cell of the whole maze at the north west corner of a chunk.
+    pub fn chunk_origin(&self, cx: usize, cy: usize) -> (usize, usize) {
+        (cx * self.chunk_size, cy * self.chunk_size)
+    }
+
+    // Get the width and height of a chunk.
+    pub fn chunk_dimensions(&self, cx: usize, cy: usize) -> (usize, usize) {
+        let (x0, y0) = self.chunk_origin(cx, cy);
+        (self.chunk_size.min(self.width - x0), self.chunk_size.min(self.height - y0))
+    }
+
+    // Derive the seed of a chunk or seam from the maze's seed, so each is independent of the others.
+    fn derive_seed(&self, what: &str, cx: usize, cy: usize) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}:{}:{},{}", self.seed, what, cx, cy).as_bytes());
+        u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap())
+    }
+
+    // Get the offsets along a seam of its openings, counted from the chunk's north or west edge.
+    fn openings(&self, cx: usize, cy: usize, seam: Seam) -> Vec<usize> {
+        let (width, height) = self.chunk_dimensions(cx, cy);
+        let (what, length) = match seam {
+            Seam::East => ("east", height),
+            Seam::South => ("south", width),
+        };
+        let mut rng = MazeRng::seed_from_u64(self.derive_seed(what, cx, cy));
+        let mut openings: Vec<usize> = (0..self.seam_openings.max(1)).map(|_| rng.gen_range(0..length)).collect();
+        openings.sort_unstable();
+        openings.dedup();
+        openings
+    }
+
+    // Generate a chunk, with its seams opened.
+    fn generate_chunk(&self, cx: usize, cy: usize) -> Maze {
+        let (width, height) = self.chunk_dimensions(cx, cy);
+        let mut chunk = Maze::new(width, height);
+        chunk.algorithm = self.algorithm;
+        chunk.braid = self.braid;
+        chunk.rough = self.rough;
+        chunk.generate_with_seed(self.derive_seed("chunk", cx, cy));
+
+        let (across, down) = self.chunks_across();
+        if cx + 1 < across {
+            for y in self.openings(cx, cy, Seam::East) {
+                chunk.grid[width - 1][y].walls[1] = false;
+            }
+        }
+        if cx > 0 {
+            for y in self.openings(cx - 1, cy, Seam::East) {
+                chunk.grid[0][y].walls[3] = false;
+            }
+        }
+        if cy + 1 < down {
+            for x in self.openings(cx, cy, Seam::South) {
+                chunk.grid[x][height - 1].walls[2] = false;
+            }
+        }
+        if cy > 0 {
+            for x in self.openings(cx, cy - 1, Seam::South) {
+                chunk.grid[x][0].walls[0] = false;
+            }
+        }
+        chunk
+    }
+
+    // Get a chunk, generating it if it is not already held. Once more than max_chunks are held,
+    // the oldest are dropped, to be generated again if they are needed.
+    pub fn chunk(&mut self, cx: usize, cy: usize) -> &Maze {
+        if !self.chunks.contains_key(&(cx, cy)) {
+            while self.generated.len() >= self.max_chunks.max(1) {
+                let oldest = self.generated.pop_front().unwrap();
+                self.chunks.remove(&oldest);
+            }
+            let chunk = self.generate_chunk(cx, cy);
+            self.chunks.insert((cx, cy), chunk);
+            self.generated.push_back((cx, cy));
+        }
+        &self.chunks[&(cx, cy)]
+    }
+
+    // Number of chunks currently generated and held.
+    pub fn generated_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Get a cell of the whole maze, with its coordinates in the whole maze.
+    pub fn cell(&mut self, x: usize, y: usize) -> Cell {
+        let ((cx, cy), (lx, ly)) = self.locate(x, y);
+        Cell { x, y, ..self.chunk(cx, cy).grid[lx][ly] }
+    }
+
+    // Check whether the wall between two adjacent cells of the whole maze is present.
+    pub fn wall_between(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) -> bool {
+        let side = match (x2 as isize - x1 as isize, y2 as isize - y1 as isize) {
+            (0, -1) => 0,
+            (1, 0) => 1,
+            (0, 1) => 2,
+            (-1, 0) => 3,
+            _ => return true, // Not adjacent.
+        };
+        self.cell(x1, y1).walls[side]
+    }
+
+    // Get the neighbors of a cell of the whole maze that can be reached without passing through a wall.
+    pub fn open_neighbors(&mut self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let walls = self.cell(x, y).walls;
+        let mut neighbors = vec![];
+        if y > 0 && !walls[0] {
+            neighbors.push((x, y - 1));
+        }
+        if x + 1 < self.width && !walls[1] {
+            neighbors.push((x + 1, y));
+        }
+        if y + 1 < self.height && !walls[2] {
+            neighbors.push((x, y + 1));
+        }
+        if x > 0 && !walls[3] {
+            neighbors.push((x - 1, y));
+        }
+        neighbors
+    }
+
+    // Generate the masked view of a chunk, from a mask over the chunk's cells indexed [x][y]. Seam
+    // openings show as open walls on the edge of the chunk.
+    pub fn get_masked_chunk(&mut self, cx: usize, cy: usize, mask: &[Vec<bool>]) -> Maze {
+        self.chunk(cx, cy).get_masked_maze(mask)
+    }
+
+    // Commit to the full layout of a chunk, seams included, and to where it lies in the maze.
+    pub fn commit_chunk<R: RngCore>(&mut self, cx: usize, cy: usize, rng: &mut R) -> (Commitment, Opening) {
+        let shape = ((self.width, self.height), self.chunk_size, (cx, cy));
+        commitment::commit(Domain::Chunk, &(shape, self.chunk(cx, cy)), rng)
+    }
+
+    // Get the commitment to a chunk under a given opening, to check a revealed chunk against.
+    pub fn chunk_commitment(&mut self, cx: usize, cy: usize, opening: &Opening) -> Commitment {
+        let shape = ((self.width, self.height), self.chunk_size, (cx, cy));
+        commitment::commit_with(Domain::Chunk, &(shape, self.chunk(cx, cy)), opening)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seed: u64) -> ChunkedMaze {
+        let mut maze = ChunkedMaze::new(40, 30, seed);
+        maze.chunk_size = 16;
+        maze.braid = 0.25;
+        maze
+    }
+
+    #[test]
+    fn test_chunks_are_deterministic() {
+        let opening = Opening { nonce: [7; 32] };
+        let mut first = sample(9);
+        let mut second = sample(9);
+        // Generating chunks in a different order, or again after eviction, changes nothing.
+        second.max_chunks = 1;
+        let _ = second.chunk(2, 1);
+        for (cx, cy) in [(1, 1), (0, 0), (2, 1)] {
+            assert_eq!(first.chunk(cx, cy).grid, second.chunk(cx, cy).grid);
+            assert_eq!(first.chunk_commitment(cx, cy, &opening), second.chunk_commitment(cx, cy, &opening));
+        }
+        assert_eq!(second.generated_chunks(), 1);
+        assert_eq!(first.chunk_dimensions(2, 1), (8, 14));
+        assert_ne!(first.chunk_commitment(0, 0, &opening), sample(10).chunk_commitment(0, 0, &opening));
+    }
+
+    #[test]
+    fn test_seams_connect_chunks() {
+        let mut maze = sample(4);
+        maze.max_chunks = 2;
+        let mut reachable = vec![vec![false; 30]; 40];
+        let mut stack = vec![(0, 0)];
+        reachable[0][0] = true;
+        while let Some((x, y)) = stack.pop() {
+            for (nx, ny) in maze.open_neighbors(x, y) {
+                // Both sides of every wall agree, across seams too.
+                assert!(!maze.wall_between(nx, ny, x, y));
+                if !reachable[nx][ny] {
+                    reachable[nx][ny] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        assert!(reachable.iter().flatten().all(|&r| r));
+        assert!(maze.generated_chunks() <= 2);
+    }
+
+    #[test]
+    fn test_masked_chunk_view() {
+        let mut maze = sample(6);
+        let (width, height) = maze.chunk_dimensions(1, 0);
+        let mut mask = vec![vec![false; height]; width];
+        mask[0][0] = true;
+        let view = maze.get_masked_chunk(1, 0, &mask);
+        assert_eq!(view.seed, None);
+        assert_eq!(view.grid[0][0], maze.chunk(1, 0).grid[0][0]);
+        assert_eq!(view.grid[1][1], Cell::new(1, 1));
+        assert_eq!(maze.chunk_origin(1, 0), (16, 0));
+        assert_eq!(maze.cell(16, 0).walls, view.grid[0][0].walls);
+    }
+}
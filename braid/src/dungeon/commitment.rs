@@ -0,0 +1,193 @@
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+/*
+ * - Commitment: A hash that hides a value until it is opened and binds the committer to it. Used by
+ *   the dungeon for its maze layout and solution path, and by players for their explorations.
+ * - Blinding: Every commitment mixes in a fresh random nonce, so a value from a small set, like a
+ *   cell of a 10x10 maze or a short path through it, cannot be found by hashing every candidate.
+ * - Domain Separation: Each kind of commitment hashes its own tag first, so a commitment made for
+ *   one purpose can never be opened as another.
+ * - Encoding: Values are encoded unambiguously before hashing. Numbers are fixed-width and
+ *   sequences carry their length, so no two different values share an encoding.
+ * - Commit, Open, Verify: commit draws the nonce and returns the commitment with its opening, to be
+ *   kept secret until the value is revealed; verify checks a revealed value and opening against
+ *   the commitment.
+ */
+
+// What a commitment is for, hashed first so commitments for different purposes never collide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Domain {
+    SolutionPath,   // The dungeon's solution path through a maze.
+    LayeredPath,    // The dungeon's solution path through a maze of several floors.
+    Layout,         // The full layout of a maze.
+    LayeredLayout,  // The full layout of a maze of several floors.
+    Chunk,          // The full layout of one chunk of a chunked maze.
+    Exploration,    // A player's exploration mask.
+}
+
+impl Domain {
+    fn tag(&self) -> &'static [u8] {
+        match self {
+            Domain::SolutionPath => b"braid/solution-path/v1",
+            Domain::LayeredPath => b"braid/layered-path/v1",
+            Domain::Layout => b"braid/layout/v1",
+            Domain::LayeredLayout => b"braid/layered-layout/v1",
+            Domain::Chunk => b"braid/chunk/v1",
+            Domain::Exploration => b"braid/exploration/v1",
+        }
+    }
+}
+
+// A commitment to a value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Commitment(pub [u8; 32]);
+
+// The secret needed, together with the value, to open a commitment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Opening {
+    pub nonce: [u8; 32],        // Random blinding mixed into the commitment.
+}
+
+// A value with an unambiguous byte encoding.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_be_bytes());
+    }
+}
+
+// As a u64, so the encoding does not depend on the platform.
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+// Prefixed with whether there is a value, so None and Some never share an encoding.
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+}
+
+// Prefixed with the length, so [[a], [b, c]] and [[a, b], [c]] encode differently.
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+// Hash a value under a domain with the given opening.
+pub fn commit_with<T: Encode + ?Sized>(domain: Domain, value: &T, opening: &Opening) -> Commitment {
+    let tag = domain.tag();
+    let mut bytes = vec![];
+    tag.len().encode(&mut bytes);
+    bytes.extend(tag);
+    bytes.extend(opening.nonce);
+    value.encode(&mut bytes);
+    Commitment(Sha256::digest(&bytes).into())
+}
+
+// Commit to a value under a domain with a fresh random nonce. The opening stays with the committer
+// until the value is revealed.
+pub fn commit<T: Encode + ?Sized, R: RngCore>(domain: Domain, value: &T, rng: &mut R) -> (Commitment, Opening) {
+    let mut nonce = [0; 32];
+    rng.fill_bytes(&mut nonce);
+    let opening = Opening { nonce };
+    (commit_with(domain, value, &opening), opening)
+}
+
+// Check a revealed value and its opening against a commitment.
+pub fn verify<T: Encode + ?Sized>(domain: Domain, commitment: &Commitment, value: &T, opening: &Opening) -> bool {
+    commit_with(domain, value, opening) == *commitment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::dungeon::maze::MazeRng;
+
+    #[test]
+    fn test_commit_open_verify() {
+        let mut rng = MazeRng::seed_from_u64(1);
+        let path: Vec<(usize, usize)> = vec![(0, 0), (0, 1), (1, 1)];
+        let (commitment, opening) = commit(Domain::SolutionPath, &path, &mut rng);
+        assert!(verify(Domain::SolutionPath, &commitment, &path, &opening));
+        assert!(!verify(Domain::SolutionPath, &commitment, &path[..2], &opening));
+        assert!(!verify(Domain::LayeredPath, &commitment, &path, &opening));
+        assert!(!verify(Domain::SolutionPath, &commitment, &path, &Opening { nonce: [0; 32] }));
+
+        // The same value committed twice gives unrelated commitments.
+        let (again, _) = commit(Domain::SolutionPath, &path, &mut rng);
+        assert_ne!(again, commitment);
+    }
+
+    #[test]
+    fn test_encoding_is_unambiguous() {
+        let encode = |value: &dyn Fn(&mut Vec<u8>)| {
+            let mut out = vec![];
+            value(&mut out);
+            out
+        };
+        let split = encode(&|out| vec![vec![1u8], vec![2, 3]].encode(out));
+        let moved = encode(&|out| vec![vec![1u8, 2], vec![3]].encode(out));
+        assert_ne!(split, moved);
+        // "1,23" and "12,3" formatted as strings would collide; fixed-width numbers do not.
+        assert_ne!(encode(&|out| (1usize, 23usize).encode(out)), encode(&|out| (12usize, 3usize).encode(out)));
+        assert_eq!(encode(&|out| (1usize, 2usize).encode(out)).len(), 16);
+    }
+}
@@ -0,0 +1,248 @@
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::{Cell, Maze};
+use crate::dungeon::topology::Topology;
+
+/*
+ * - Compact Maze: Wall storage for square mazes that keeps each shared wall once, as a single
+ *   bit, instead of a Cell per position with both sides of every wall.
+ * - Vertical Walls: Bit x * height + y is the wall between (x, y) and (x + 1, y).
+ * - Horizontal Walls: Bit x * (height - 1) + y is the wall between (x, y) and (x, y + 1).
+ * - Boundary Walls: Always present, so they are not stored.
+ * - Neighbor Queries: Answered straight from the bits, without building cells.
+ * - Conversion: To and from Maze. Only the walls, starts and treasure are kept, so doors, keys,
+ *   features and terrain are dropped.
+ */
+
+// A fixed-size set of bits packed into 64-bit words.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitSet {
+    len: usize,                 // Number of bits.
+    words: Vec<u64>,            // Bit i is bit i % 64 of word i / 64.
+}
+
+impl BitSet {
+    // Create a set of len bits, all set to the given value.
+    pub fn new(len: usize, value: bool) -> Self {
+        let mut words = vec![if value { u64::MAX } else { 0 }; len.div_ceil(64)];
+        // Keep the unused bits of the last word clear, so equal sets compare equal.
+        if value && !len.is_multiple_of(64) {
+            *words.last_mut().unwrap() = (1 << (len % 64)) - 1;
+        }
+        BitSet { len, words }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    // Number of bits set.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+// Representation of a square maze's walls as bits.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactMaze {
+    pub width: usize,           // Width of the maze.
+    pub height: usize,          // Height of the maze.
+    vertical: BitSet,           // Walls between horizontally adjacent cells.
+    horizontal: BitSet,         // Walls between vertically adjacent cells.
+    pub starts: Vec<(usize, usize)>, // Start cells.
+    pub treasure: (usize, usize), // Cell holding the treasure.
+}
+
+impl CompactMaze {
+    // Create a maze with every wall present.
+    pub fn new(width: usize, height: usize) -> Self {
+        CompactMaze {
+            width,
+            height,
+            vertical: BitSet::new(width.saturating_sub(1) * height, true),
+            horizontal: BitSet::new(width * height.saturating_sub(1), true),
+            starts: vec![],
+            treasure: (width / 2, height / 2),
+        }
+    }
+
+    // Pack the walls of a maze. None if the maze is not square-tiled, since only square walls
+    // split into horizontal and vertical ones.
+    pub fn from_maze(maze: &Maze) -> Option<Self> {
+        if maze.topology != Topology::Square {
+            return None;
+        }
+        let mut compact = CompactMaze::new(maze.width, maze.height);
+        for x in 0..maze.width {
+            for y in 0..maze.height {
+                let walls = maze.grid[x][y].walls;
+                if x + 1 < maze.width {
+                    compact.vertical.set(compact.vertical_index(x, y), walls[1]);
+                }
+                if y + 1 < maze.height {
+                    compact.horizontal.set(compact.horizontal_index(x, y), walls[2]);
+                }
+            }
+        }
+        compact.starts = maze.starts.clone();
+        compact.treasure = maze.treasure;
+        Some(compact)
+    }
+
+    // Unpack into a Maze, with both sides of every wall and the boundary walls filled in.
+    pub fn to_maze(&self) -> Maze {
+        let mut maze = Maze::new(self.width, self.height);
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let walls = self.walls(x, y);
+                maze.grid[x][y] = Cell { visited: true, ..Cell::new(x, y) };
+                maze.grid[x][y].walls[..4].copy_from_slice(&walls);
+            }
+        }
+        maze.starts = self.starts.clone();
+        maze.treasure = self.treasure;
+        maze
+    }
+
+    fn vertical_index(&self, x: usize, y: usize) -> usize {
+        x * self.height + y
+    }
+
+    fn horizontal_index(&self, x: usize, y: usize) -> usize {
+        x * (self.height - 1) + y
+    }
+
+    // Get the walls of a cell as [north, east, south, west].
+    pub fn walls(&self, x: usize, y: usize) -> [bool; 4] {
+        [
+            y == 0 || self.horizontal.get(self.horizontal_index(x, y - 1)),
+            x + 1 == self.width || self.vertical.get(self.vertical_index(x, y)),
+            y + 1 == self.height || self.horizontal.get(self.horizontal_index(x, y)),
+            x == 0 || self.vertical.get(self.vertical_index(x - 1, y)),
+        ]
+    }
+
+    // Check whether the wall between two adjacent cells is present. Cells that are not adjacent
+    // are always walled off.
+    pub fn wall_between(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> bool {
+        let ((x1, y1), (x2, y2)) = ((x1, y1).min((x2, y2)), (x1, y1).max((x2, y2)));
+        if x2 >= self.width || y2 >= self.height {
+            return true;
+        }
+        if x1 + 1 == x2 && y1 == y2 {
+            self.vertical.get(self.vertical_index(x1, y1))
+        } else if x1 == x2 && y1 + 1 == y2 {
+            self.horizontal.get(self.horizontal_index(x1, y1))
+        } else {
+            true
+        }
+    }
+
+    // Remove the wall between two adjacent cells. Does nothing if the cells are not adjacent.
+    pub fn remove_wall(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        let ((x1, y1), (x2, y2)) = ((x1, y1).min((x2, y2)), (x1, y1).max((x2, y2)));
+        if x2 >= self.width || y2 >= self.height {
+            return;
+        }
+        if x1 + 1 == x2 && y1 == y2 {
+            let i = self.vertical_index(x1, y1);
+            self.vertical.set(i, false);
+        } else if x1 == x2 && y1 + 1 == y2 {
+            let i = self.horizontal_index(x1, y1);
+            self.horizontal.set(i, false);
+        }
+    }
+
+    // Get the neighbors of a cell that can be reached without passing through a wall.
+    pub fn open_neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let [north, east, south, west] = self.walls(x, y);
+        let mut neighbors = Vec::with_capacity(4);
+        if !north {
+            neighbors.push((x, y - 1));
+        }
+        if !east {
+            neighbors.push((x + 1, y));
+        }
+        if !south {
+            neighbors.push((x, y + 1));
+        }
+        if !west {
+            neighbors.push((x - 1, y));
+        }
+        neighbors
+    }
+
+    // Number of passages, counting each opened wall once.
+    pub fn passages(&self) -> usize {
+        (self.vertical.len() - self.vertical.count_ones()) + (self.horizontal.len() - self.horizontal.count_ones())
+    }
+
+    // Bytes taken by the wall bits.
+    pub fn wall_bytes(&self) -> usize {
+        (self.vertical.words.len() + self.horizontal.words.len()) * std::mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::commitment::Opening;
+
+    #[test]
+    fn test_round_trip_through_maze() {
+        let opening = Opening { nonce: [7; 32] };
+        let mut maze = Maze::new(13, 9);
+        maze.braid = 0.5;
+        maze.generate_with_seed(3);
+        let compact = maze.to_compact().unwrap();
+        let unpacked = compact.to_maze();
+        for x in 0..13 {
+            for y in 0..9 {
+                assert_eq!(unpacked.grid[x][y].walls, maze.grid[x][y].walls);
+                let mut expected = maze.open_neighbors(x, y);
+                let mut neighbors = compact.open_neighbors(x, y);
+                expected.sort_unstable();
+                neighbors.sort_unstable();
+                assert_eq!(neighbors, expected);
+            }
+        }
+        assert!(unpacked.validate().is_empty());
+        assert_eq!(unpacked.layout_commitment(&opening), maze.layout_commitment(&opening));
+
+        // A perfect maze has one passage fewer than it has cells.
+        let perfect = CompactMaze::from_maze(&Maze::from_seed(13, 9, 3)).unwrap();
+        assert_eq!(perfect.passages(), 13 * 9 - 1);
+    }
+
+    #[test]
+    fn test_walls_take_a_bit_each() {
+        let mut compact = CompactMaze::new(100, 100);
+        // 99 * 100 walls each way, in 155 words apiece.
+        assert_eq!(compact.wall_bytes(), 2 * 155 * 8);
+        assert!(compact.wall_between(5, 5, 6, 5));
+        compact.remove_wall(6, 5, 5, 5);
+        assert!(!compact.wall_between(5, 5, 6, 5));
+        assert_eq!(compact.open_neighbors(5, 5), vec![(6, 5)]);
+        assert!(compact.wall_between(0, 0, 2, 0));
+        assert!(compact.wall_between(99, 0, 100, 0));
+
+        let mut hex = Maze::new(4, 4);
+        hex.topology = Topology::Hex;
+        assert!(CompactMaze::from_maze(&hex).is_none());
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::dungeon::maze::Maze;
+use crate::dungeon::render::shared_wall;
+
+/*
+ * - Export Options: Scale of the image and the exploration trace to overlay, if any.
+ * - Scene: Resolution independent shapes describing the maze, shared by both output formats.
+ * - SVG: Writes the scene as an SVG document.
+ * - PNG: Rasterizes the scene on the CPU and encodes it as a PNG image.
+ *
+ * Exports are meant as visual evidence for dispute reports, so a masked maze (see
+ * Maze::get_masked_maze) can be exported together with the mask it was built from, and only the
+ * walls the player was actually shown are drawn.
+ */
+
+type Color = [u8; 4]; // RGBA.
+
+const BACKGROUND: Color = [255, 255, 255, 255];
+const WALL: Color = [0, 0, 0, 255];
+const UNEXPLORED: Color = [160, 160, 160, 255];
+const EXPLORED: Color = [255, 236, 179, 255];
+const HEAT: Color = [220, 40, 30, 0];             // Alpha is scaled by how often a cell was visited.
+const START: Color = [60, 170, 80, 255];
+const TREASURE: Color = [240, 190, 20, 255];
+const PATH: Color = [30, 90, 220, 200];
+
+// How to export a maze.
+pub struct ExportOptions<'a> {
+    pub cell_size: u32,                     // Side of a cell in pixels.
+    pub mask: Option<&'a [Vec<bool>]>,      // Explored cells. Without a mask every cell is explored.
+    pub path: &'a [(usize, usize)],         // The player's path, drawn as a line and a visit heatmap.
+}
+
+impl<'a> Default for ExportOptions<'a> {
+    fn default() -> Self {
+        ExportOptions { cell_size: 24, mask: None, path: &[] }
+    }
+}
+
+// A shape in image coordinates.
+enum Shape {
+    Polygon { points: Vec<(f64, f64)>, color: Color },
+    Line { from: (f64, f64), to: (f64, f64), width: f64, color: Color },
+}
+
+// An image described as shapes painted in order.
+struct Scene {
+    width: u32,
+    height: u32,
+    shapes: Vec<Shape>,
+}
+
+impl Scene {
+    fn new(maze: &Maze, options: &ExportOptions) -> Self {
+        let size = options.cell_size.max(4) as f64;
+        let wall_width = (size / 8.0).max(1.0);
+        let margin = wall_width / 2.0;
+        let topology = maze.topology;
+        // Outline of a cell in image coordinates, and its center.
+        let outline = |(x, y): (usize, usize)| -> Vec<(f64, f64)> {
+            topology.polygon(x, y).into_iter().map(|(px, py)| (margin + px * size, margin + py * size)).collect()
+        };
+        let center = |cell: (usize, usize)| {
+            let points = outline(cell);
+            let n = points.len() as f64;
+            (points.iter().map(|p| p.0).sum::<f64>() / n, points.iter().map(|p| p.1).sum::<f64>() / n)
+        };
+        let mut shapes = vec![];
+
+        // Cell backgrounds: fog for unexplored cells, a tint for explored ones, and a heatmap of
+        // visits along the path on top.
+        let mut visits = vec![vec![0u32; maze.height]; maze.width];
+        for &(x, y) in options.path {
+            visits[x][y] += 1;
+        }
+        let max_visits = visits.iter().flatten().copied().max().unwrap_or(0);
+        for x in 0..maze.width {
+            for y in 0..maze.height {
+                let cell = |color| Shape::Polygon { points: outline((x, y)), color };
+                match options.mask {
+                    Some(mask) if !mask[x][y] => shapes.push(cell(UNEXPLORED)),
+                    Some(_) => shapes.push(cell(EXPLORED)),
+                    None => {}
+                }
+                if visits[x][y] > 0 {
+                    let mut heat = HEAT;
+                    heat[3] = (60 + 160 * visits[x][y] / max_visits) as u8;
+                    shapes.push(cell(heat));
+                }
+            }
+        }
+
+        // Starts and the treasure as the cell's outline shrunk to half size around its center.
+        let marker = |cell: (usize, usize), color| {
+            let (cx, cy) = center(cell);
+            let points = outline(cell).into_iter().map(|(px, py)| ((px + cx) / 2.0, (py + cy) / 2.0)).collect();
+            Shape::Polygon { points, color }
+        };
+        for &start in &maze.starts {
+            shapes.push(marker(start, START));
+        }
+        shapes.push(marker(maze.treasure, TREASURE));
+
+        // The path through the centers of the cells it visits.
+        for step in options.path.windows(2) {
+            shapes.push(Shape::Line { from: center(step[0]), to: center(step[1]), width: wall_width, color: PATH });
+        }
+
+        // Walls along the sides of each cell's outline. A shared wall is drawn by the cell that
+        // comes first in grid order, so it is only drawn once.
+        let sides = topology.sides();
+        for x in 0..maze.width {
+            for y in 0..maze.height {
+                let walls = maze.grid[x][y].walls;
+                let points = outline((x, y));
+                for side in 0..sides {
+                    let wall = match topology.neighbor(x, y, side, maze.width, maze.height) {
+                        Some(n) if n < (x, y) => continue,
+                        Some(n) => {
+                            let back = maze.grid[n.0][n.1].walls[topology.opposite(side)];
+                            shared_wall(options.mask, (x, y), walls[side], n, back)
+                        }
+                        None => walls[side],
+                    };
+                    if wall {
+                        let (from, to) = (points[side], points[(side + 1) % sides]);
+                        shapes.push(Shape::Line { from, to, width: wall_width, color: WALL });
+                    }
+                }
+            }
+        }
+
+        let (width, height) = topology.extent(maze.width, maze.height);
+        Scene {
+            width: (2.0 * margin + width * size).ceil() as u32,
+            height: (2.0 * margin + height * size).ceil() as u32,
+            shapes,
+        }
+    }
+}
+
+// Format a color as an SVG paint with its opacity.
+fn svg_paint(attribute: &str, color: Color) -> String {
+    format!(
+        "{}=\"rgb({},{},{})\" {}-opacity=\"{:.3}\"",
+        attribute, color[0], color[1], color[2], attribute, color[3] as f64 / 255.0
+    )
+}
+
+// Export a maze as an SVG document.
+pub fn to_svg(maze: &Maze, options: &ExportOptions) -> String {
+    let scene = Scene::new(maze, options);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = scene.width,
+        h = scene.height
+    );
+    let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" {}/>", svg_paint("fill", BACKGROUND));
+    for shape in &scene.shapes {
+        let _ = match *shape {
+            Shape::Polygon { ref points, color } => {
+                let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                writeln!(svg, "<polygon points=\"{}\" {}/>", points.join(" "), svg_paint("fill", color))
+            }
+            Shape::Line { from, to, width, color } => writeln!(
+                svg,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke-width=\"{}\" stroke-linecap=\"square\" {}/>",
+                from.0, from.1, to.0, to.1, width, svg_paint("stroke", color)
+            ),
+        };
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// An RGBA pixel buffer.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, color: Color) -> Self {
+        let pixels = color.iter().copied().cycle().take((width * height * 4) as usize).collect();
+        Canvas { width, height, pixels }
+    }
+
+    // Alpha blend a color over one pixel.
+    fn blend(&mut self, x: u32, y: u32, color: Color) {
+        let i = ((y * self.width + x) * 4) as usize;
+        let alpha = color[3] as u32;
+        for (pixel, &channel) in self.pixels[i..i + 3].iter_mut().zip(&color[..3]) {
+            *pixel = ((channel as u32 * alpha + *pixel as u32 * (255 - alpha)) / 255) as u8;
+        }
+        self.pixels[i + 3] = 255;
+    }
+
+    // Fill the pixels whose centers lie within the polygon, by the even-odd rule.
+    fn fill_polygon(&mut self, points: &[(f64, f64)], color: Color) {
+        let bound = |coord: fn(&(f64, f64)) -> f64, max: u32| {
+            let low = points.iter().map(coord).fold(f64::INFINITY, f64::min);
+            let high = points.iter().map(coord).fold(f64::NEG_INFINITY, f64::max);
+            ((low - 0.5).ceil().max(0.0) as u32, ((high - 0.5).ceil().max(0.0) as u32).min(max))
+        };
+        let (x0, x1) = bound(|p| p.0, self.width);
+        let (y0, y1) = bound(|p| p.1, self.height);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let (cx, cy) = (px as f64 + 0.5, py as f64 + 0.5);
+                let mut inside = false;
+                for (i, &(ax, ay)) in points.iter().enumerate() {
+                    let (bx, by) = points[(i + 1) % points.len()];
+                    if (ay > cy) != (by > cy) && cx < ax + (cy - ay) * (bx - ax) / (by - ay) {
+                        inside = !inside;
+                    }
+                }
+                if inside {
+                    self.blend(px, py, color);
+                }
+            }
+        }
+    }
+
+    // Draw a line with square caps by marking every pixel within a square brush swept along it.
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), width: f64, color: Color) {
+        let half = width / 2.0;
+        let x0 = ((from.0.min(to.0) - half).floor().max(0.0) as u32).min(self.width);
+        let y0 = ((from.1.min(to.1) - half).floor().max(0.0) as u32).min(self.height);
+        let x1 = ((from.0.max(to.0) + half).ceil().max(0.0) as u32).min(self.width);
+        let y1 = ((from.1.max(to.1) + half).ceil().max(0.0) as u32).min(self.height);
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length = (dx * dx + dy * dy).sqrt().max(f64::EPSILON);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                // Position of the pixel center along the line and away from it.
+                let (cx, cy) = (px as f64 + 0.5 - from.0, py as f64 + 0.5 - from.1);
+                let along = (cx * dx + cy * dy) / length;
+                let across = (cx * dy - cy * dx).abs() / length;
+                if across <= half && along >= -half && along <= length + half {
+                    self.blend(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+// Rasterize a maze into RGBA pixels, returning the width, height and pixel data.
+fn rasterize(maze: &Maze, options: &ExportOptions) -> (u32, u32, Vec<u8>) {
+    let scene = Scene::new(maze, options);
+    let mut canvas = Canvas::new(scene.width, scene.height, BACKGROUND);
+    for shape in &scene.shapes {
+        match *shape {
+            Shape::Polygon { ref points, color } => canvas.fill_polygon(points, color),
+            Shape::Line { from, to, width, color } => canvas.line(from, to, width, color),
+        }
+    }
+    (canvas.width, canvas.height, canvas.pixels)
+}
+
+// Export a maze as a PNG image.
+pub fn to_png(maze: &Maze, options: &ExportOptions) -> io::Result<Vec<u8>> {
+    let (width, height, pixels) = rasterize(maze, options);
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&pixels).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(png)
+}
+
+// Write a maze to an SVG file.
+pub fn save_svg<P: AsRef<Path>>(maze: &Maze, options: &ExportOptions, path: P) -> io::Result<()> {
+    fs::write(path, to_svg(maze, options))
+}
+
+// Write a maze to a PNG file.
+pub fn save_png<P: AsRef<Path>>(maze: &Maze, options: &ExportOptions, path: P) -> io::Result<()> {
+    fs::write(path, to_png(maze, options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::topology::Topology;
+
+    #[test]
+    fn test_svg_draws_each_wall_once() {
+        // Two cells joined by a passage have six wall segments around them.
+        let mut maze = Maze::new(2, 1);
+        maze.remove_wall(0, 0, 1, 0);
+        maze.starts = vec![(0, 0)];
+        maze.treasure = (1, 0);
+        let svg = to_svg(&maze, &ExportOptions::default());
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<line").count(), 6);
+
+        // A path adds one line per step.
+        let path = [(0, 0), (1, 0)];
+        let svg = to_svg(&maze, &ExportOptions { path: &path, ..Default::default() });
+        assert_eq!(svg.matches("<line").count(), 7);
+    }
+
+    #[test]
+    fn test_png_rasterization() {
+        let maze = Maze::from_seed(5, 4, 1);
+        let options = ExportOptions { cell_size: 16, ..Default::default() };
+        let (width, height, pixels) = rasterize(&maze, &options);
+        assert_eq!((width, height), (5 * 16 + 2, 4 * 16 + 2));
+        // The outer wall runs through the top left corner, and the inside of a cell is clear.
+        assert_eq!(&pixels[..4], &WALL);
+        let i = ((5 * width + 21) * 4) as usize;
+        assert_eq!(&pixels[i..i + 4], &BACKGROUND);
+
+        let png = to_png(&maze, &options).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[16..24], &[0, 0, 0, 82, 0, 0, 0, 66]);
+    }
+
+    #[test]
+    fn test_hex_and_triangle_exports() {
+        for topology in [Topology::Hex, Topology::Triangle] {
+            let mut maze = Maze::new(4, 3);
+            maze.topology = topology;
+            maze.generate_with_seed(2);
+            // A perfect maze keeps every wall but its 11 passages, and each wall is drawn once.
+            let (mut shared, mut boundary) = (0, 0);
+            for x in 0..4 {
+                for y in 0..3 {
+                    for side in 0..topology.sides() {
+                        match topology.neighbor(x, y, side, 4, 3) {
+                            Some(_) => shared += 1,
+                            None => boundary += 1,
+                        }
+                    }
+                }
+            }
+            let svg = to_svg(&maze, &ExportOptions::default());
+            assert_eq!(svg.matches("<line").count(), shared / 2 - 11 + boundary, "{:?}", topology);
+            assert!(svg.contains("<polygon"));
+
+            let (width, height) = topology.extent(4, 3);
+            let (w, h, _) = rasterize(&maze, &ExportOptions { cell_size: 20, ..Default::default() });
+            assert_eq!((w, h), ((width * 20.0 + 2.5).ceil() as u32, (height * 20.0 + 2.5).ceil() as u32));
+        }
+    }
+}
@@ -0,0 +1,580 @@
+use std::fmt;
+use std::io;
+use crate::dungeon::generator::{Algorithm, Selection};
+use crate::dungeon::maze::{Cell, Feature, Maze, StartLayout, Terrain, MAX_KEYS};
+use crate::dungeon::topology::Topology;
+
+/*
+ * Versioned binary maze format, so dungeons can be pre-generated, archived and re-served.
+ *
+ * All integers are big endian.
+ *
+ *   magic           4 bytes   "BRDM"
+ *   version         u8        FORMAT_VERSION
+ *   topology        u8        0 square, 1 hex, 2 triangle
+ *   width, height   u32, u32
+ *   seed            u8 flag, followed by a u64 if the flag is 1
+ *   algorithm       u8 id, u8 parameter (see algorithm_id)
+ *   braid           u64       bits of the f64 braid fraction
+ *   doors setting   u8        number of doors generation places
+ *   feature settings u32 one-ways, u32 teleporter pairs, u32 traps
+ *   rough           u64       bits of the f64 rough terrain fraction
+ *   start layout    u8 tag (0 shared, 1 per player), u32 player count
+ *   starts          u32 count, then count (u32 x, u32 y) pairs
+ *   treasure        u32 x, u32 y
+ *   walls           1 bit per owned side, cells in grid order (x major) and sides in side order,
+ *                   packed most significant bit first and padded with zeros to a whole byte
+ *   doors           u32 count, then count (u32 x, u32 y, u8 side, u8 key), each door stored
+ *                   on the cell owning its side
+ *   keys            u32 count, then count (u32 x, u32 y, u8 key)
+ *   one-ways        u32 count, then count (u32 x, u32 y, u8 side), each stored on the cell the
+ *                   passage leads out of
+ *   features        u32 count, then count (u32 x, u32 y, u8 kind) followed by u32 x, u32 y of
+ *                   the paired cell for a teleporter (kind 0), u8 turns for a trap (kind 1) and
+ *                   nothing for a pit (kind 2)
+ *   terrain         u32 count, then count (u32 x, u32 y, u8 terrain) for every cell that is not
+ *                   a corridor, with 1 mud, 2 water and 3 rubble
+ *
+ * A cell owns the sides whose neighbor comes after it in grid order. The other walls are not
+ * stored: they belong to the neighbor, or are boundary walls, which are always present.
+ */
+
+const MAGIC: &[u8; 4] = b"BRDM";
+const FORMAT_VERSION: u8 = 1;
+
+// Map a topology to its stable id.
+fn topology_id(topology: Topology) -> u8 {
+    match topology {
+        Topology::Square => 0,
+        Topology::Hex => 1,
+        Topology::Triangle => 2,
+    }
+}
+
+// Map a stable id back to a topology.
+fn topology_from_id(id: u8) -> Option<Topology> {
+    Some(match id {
+        0 => Topology::Square,
+        1 => Topology::Hex,
+        2 => Topology::Triangle,
+        _ => return None,
+    })
+}
+
+// Map a terrain to its stable id.
+fn terrain_id(terrain: Terrain) -> u8 {
+    match terrain {
+        Terrain::Corridor => 0,
+        Terrain::Mud => 1,
+        Terrain::Water => 2,
+        Terrain::Rubble => 3,
+    }
+}
+
+// Map a stable id back to a terrain.
+fn terrain_from_id(id: u8) -> Option<Terrain> {
+    Some(match id {
+        0 => Terrain::Corridor,
+        1 => Terrain::Mud,
+        2 => Terrain::Water,
+        3 => Terrain::Rubble,
+        _ => return None,
+    })
+}
+
+// Get the sides of a cell whose walls are stored, paired with the neighbor across each, if any.
+fn stored_sides(topology: Topology, x: usize, y: usize, width: usize, height: usize) -> Vec<(usize, (usize, usize))> {
+    (0..topology.sides())
+        .filter_map(|side| topology.neighbor(x, y, side, width, height).map(|n| (side, n)))
+        .filter(|&(_, n)| n > (x, y))
+        .collect()
+}
+
+// Errors reading a maze from its binary form.
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),              // Reading the file failed.
+    BadMagic,                   // The data does not start with the maze magic bytes.
+    UnsupportedVersion(u8),     // The data was written by an unknown format version.
+    Truncated,                  // The data ended early.
+    Invalid(&'static str),      // A field holds a value no valid maze can have.
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "io error: {}", e),
+            FormatError::BadMagic => write!(f, "not a maze file"),
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported maze format version {}", v),
+            FormatError::Truncated => write!(f, "maze data is truncated"),
+            FormatError::Invalid(what) => write!(f, "invalid maze data: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> Self {
+        FormatError::Io(e)
+    }
+}
+
+// Map an algorithm to its stable id and parameter byte.
+fn algorithm_id(algorithm: Algorithm) -> (u8, u8) {
+    match algorithm {
+        Algorithm::Prim => (0, 0),
+        Algorithm::Kruskal => (1, 0),
+        Algorithm::RecursiveBacktracker => (2, 0),
+        Algorithm::Wilson => (3, 0),
+        Algorithm::Eller => (4, 0),
+        Algorithm::GrowingTree(Selection::Newest) => (5, 0),
+        Algorithm::GrowingTree(Selection::Oldest) => (5, 1),
+        Algorithm::GrowingTree(Selection::Random) => (5, 2),
+        Algorithm::GrowingTree(Selection::Mixed(newest)) => (6, newest),
+    }
+}
+
+// Map a stable id and parameter byte back to an algorithm.
+fn algorithm_from_id(id: u8, parameter: u8) -> Option<Algorithm> {
+    Some(match (id, parameter) {
+        (0, 0) => Algorithm::Prim,
+        (1, 0) => Algorithm::Kruskal,
+        (2, 0) => Algorithm::RecursiveBacktracker,
+        (3, 0) => Algorithm::Wilson,
+        (4, 0) => Algorithm::Eller,
+        (5, 0) => Algorithm::GrowingTree(Selection::Newest),
+        (5, 1) => Algorithm::GrowingTree(Selection::Oldest),
+        (5, 2) => Algorithm::GrowingTree(Selection::Random),
+        (6, newest) if newest <= 100 => Algorithm::GrowingTree(Selection::Mixed(newest)),
+        _ => return None,
+    })
+}
+
+// Encode a maze in the binary format.
+pub fn encode(maze: &Maze) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64 + (maze.width * maze.height).div_ceil(4));
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    out.push(topology_id(maze.topology));
+    out.extend_from_slice(&(maze.width as u32).to_be_bytes());
+    out.extend_from_slice(&(maze.height as u32).to_be_bytes());
+
+    match maze.seed {
+        Some(seed) => {
+            out.push(1);
+            out.extend_from_slice(&seed.to_be_bytes());
+        }
+        None => out.push(0),
+    }
+    let (id, parameter) = algorithm_id(maze.algorithm);
+    out.push(id);
+    out.push(parameter);
+    out.extend_from_slice(&maze.braid.to_bits().to_be_bytes());
+    out.push(maze.doors.min(MAX_KEYS) as u8);
+    for setting in [maze.one_ways, maze.teleporters, maze.traps] {
+        out.extend_from_slice(&(setting as u32).to_be_bytes());
+    }
+    out.extend_from_slice(&maze.rough.to_bits().to_be_bytes());
+
+    let (tag, players) = match maze.start_layout {
+        StartLayout::Shared => (0u8, 0),
+        StartLayout::PerPlayer(players) => (1u8, players),
+    };
+    out.push(tag);
+    out.extend_from_slice(&(players as u32).to_be_bytes());
+    out.extend_from_slice(&(maze.starts.len() as u32).to_be_bytes());
+    for &(x, y) in maze.starts.iter().chain(std::iter::once(&maze.treasure)) {
+        out.extend_from_slice(&(x as u32).to_be_bytes());
+        out.extend_from_slice(&(y as u32).to_be_bytes());
+    }
+
+    let mut byte = 0u8;
+    let mut bits = 0;
+    for cell in maze.grid.iter().flatten() {
+        for (side, _) in stored_sides(maze.topology, cell.x, cell.y, maze.width, maze.height) {
+            byte = (byte << 1) | cell.walls[side] as u8;
+            bits += 1;
+            if bits == 8 {
+                out.push(byte);
+                byte = 0;
+                bits = 0;
+            }
+        }
+    }
+    if bits > 0 {
+        out.push(byte << (8 - bits));
+    }
+
+    let mut doors = vec![];
+    let mut keys = vec![];
+    for cell in maze.grid.iter().flatten() {
+        for (side, _) in stored_sides(maze.topology, cell.x, cell.y, maze.width, maze.height) {
+            if let Some(key) = cell.doors[side] {
+                doors.push((cell.x, cell.y, side, key));
+            }
+        }
+        if let Some(key) = cell.key {
+            keys.push((cell.x, cell.y, key));
+        }
+    }
+    out.extend_from_slice(&(doors.len() as u32).to_be_bytes());
+    for (x, y, side, key) in doors {
+        out.extend_from_slice(&(x as u32).to_be_bytes());
+        out.extend_from_slice(&(y as u32).to_be_bytes());
+        out.push(side as u8);
+        out.push(key);
+    }
+    out.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+    for (x, y, key) in keys {
+        out.extend_from_slice(&(x as u32).to_be_bytes());
+        out.extend_from_slice(&(y as u32).to_be_bytes());
+        out.push(key);
+    }
+
+    let one_ways: Vec<(usize, usize, usize)> = maze.grid.iter()
+        .flatten()
+        .flat_map(|cell| (0..maze.topology.sides()).filter(|&side| cell.one_way[side]).map(|side| (cell.x, cell.y, side)))
+        .collect();
+    out.extend_from_slice(&(one_ways.len() as u32).to_be_bytes());
+    for (x, y, side) in one_ways {
+        out.extend_from_slice(&(x as u32).to_be_bytes());
+        out.extend_from_slice(&(y as u32).to_be_bytes());
+        out.push(side as u8);
+    }
+    let features: Vec<(usize, usize, Feature)> = maze.grid.iter()
+        .flatten()
+        .filter_map(|cell| cell.feature.map(|feature| (cell.x, cell.y, feature)))
+        .collect();
+    out.extend_from_slice(&(features.len() as u32).to_be_bytes());
+    for (x, y, feature) in features {
+        out.extend_from_slice(&(x as u32).to_be_bytes());
+        out.extend_from_slice(&(y as u32).to_be_bytes());
+        match feature {
+            Feature::Teleporter { x, y } => {
+                out.push(0);
+                out.extend_from_slice(&(x as u32).to_be_bytes());
+                out.extend_from_slice(&(y as u32).to_be_bytes());
+            }
+            Feature::Trap { turns } => out.extend_from_slice(&[1, turns]),
+            Feature::Pit => out.push(2),
+        }
+    }
+    let rough: Vec<&Cell> = maze.grid.iter().flatten().filter(|cell| cell.terrain != Terrain::Corridor).collect();
+    out.extend_from_slice(&(rough.len() as u32).to_be_bytes());
+    for cell in rough {
+        out.extend_from_slice(&(cell.x as u32).to_be_bytes());
+        out.extend_from_slice(&(cell.y as u32).to_be_bytes());
+        out.push(terrain_id(cell.terrain));
+    }
+    out
+}
+
+// Cursor over the bytes being decoded.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < n {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, FormatError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, FormatError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn position(&mut self) -> Result<(usize, usize), FormatError> {
+        Ok((self.u32()?, self.u32()?))
+    }
+}
+
+// Decode a maze from the binary format.
+pub fn decode(bytes: &[u8]) -> Result<Maze, FormatError> {
+    let mut reader = Reader { bytes };
+    if reader.take(4).map_err(|_| FormatError::BadMagic)? != MAGIC {
+        return Err(FormatError::BadMagic);
+    }
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    let topology = topology_from_id(reader.u8()?).ok_or(FormatError::Invalid("topology"))?;
+
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let cells = width.checked_mul(height).ok_or(FormatError::Invalid("dimensions"))?;
+    if cells == 0 {
+        return Err(FormatError::Invalid("dimensions"));
+    }
+
+    let seed = match reader.u8()? {
+        0 => None,
+        1 => Some(reader.u64()?),
+        _ => return Err(FormatError::Invalid("seed flag")),
+    };
+    let (id, parameter) = (reader.u8()?, reader.u8()?);
+    let algorithm = algorithm_from_id(id, parameter).ok_or(FormatError::Invalid("algorithm"))?;
+    let braid = f64::from_bits(reader.u64()?);
+    if !(0.0..=1.0).contains(&braid) {
+        return Err(FormatError::Invalid("braid"));
+    }
+    let doors = reader.u8()? as usize;
+    if doors > MAX_KEYS {
+        return Err(FormatError::Invalid("doors"));
+    }
+    let (one_ways, teleporters, traps) = (reader.u32()?, reader.u32()?, reader.u32()?);
+    let rough = f64::from_bits(reader.u64()?);
+    if !(0.0..=1.0).contains(&rough) {
+        return Err(FormatError::Invalid("rough"));
+    }
+
+    let start_layout = match (reader.u8()?, reader.u32()?) {
+        (0, _) => StartLayout::Shared,
+        (1, players) => StartLayout::PerPlayer(players),
+        _ => return Err(FormatError::Invalid("start layout")),
+    };
+    let count = reader.u32()?;
+    if count > cells {
+        return Err(FormatError::Invalid("start count"));
+    }
+    // Check the starts are all there before making room for them.
+    if reader.bytes.len() < count.checked_mul(8).ok_or(FormatError::Truncated)? {
+        return Err(FormatError::Truncated);
+    }
+    let in_bounds = |(x, y): (usize, usize)| x < width && y < height;
+    let mut starts = Vec::with_capacity(count);
+    for _ in 0..count {
+        let start = reader.position()?;
+        if !in_bounds(start) {
+            return Err(FormatError::Invalid("start"));
+        }
+        starts.push(start);
+    }
+    let treasure = reader.position()?;
+    if !in_bounds(treasure) {
+        return Err(FormatError::Invalid("treasure"));
+    }
+
+    // Count the wall bits without visiting every cell, so a header claiming a huge maze is caught
+    // before anything is made for it.
+    let stored = topology.edges(width, height).ok_or(FormatError::Truncated)?;
+    let walls = reader.take(stored.div_ceil(8))?;
+    let bit = |i: usize| walls[i / 8] & (0x80 >> (i % 8)) != 0;
+
+    let mut maze = Maze::new(width, height);
+    maze.topology = topology;
+    for x in 0..width {
+        for y in 0..height {
+            maze.grid[x][y] = Cell { visited: true, ..Cell::new(x, y) };
+        }
+    }
+    let mut i = 0;
+    for x in 0..width {
+        for y in 0..height {
+            for (side, (nx, ny)) in stored_sides(topology, x, y, width, height) {
+                let wall = bit(i);
+                maze.grid[x][y].walls[side] = wall;
+                maze.grid[nx][ny].walls[topology.opposite(side)] = wall;
+                i += 1;
+            }
+        }
+    }
+
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let (x, y) = reader.position()?;
+        let (side, key) = (reader.u8()? as usize, reader.u8()?);
+        let neighbor = in_bounds((x, y)).then(|| topology.neighbor(x, y, side, width, height)).flatten();
+        match neighbor {
+            Some((nx, ny)) if (key as usize) < MAX_KEYS => maze.set_door(x, y, nx, ny, Some(key)),
+            _ => return Err(FormatError::Invalid("door")),
+        }
+    }
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let (x, y) = reader.position()?;
+        let key = reader.u8()?;
+        if !in_bounds((x, y)) || key as usize >= MAX_KEYS {
+            return Err(FormatError::Invalid("key"));
+        }
+        maze.grid[x][y].key = Some(key);
+    }
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let (x, y) = reader.position()?;
+        let side = reader.u8()? as usize;
+        if !in_bounds((x, y)) || side >= topology.sides() {
+            return Err(FormatError::Invalid("one-way"));
+        }
+        maze.grid[x][y].one_way[side] = true;
+    }
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let (x, y) = reader.position()?;
+        let feature = match reader.u8()? {
+            0 => {
+                let (tx, ty) = reader.position()?;
+                Feature::Teleporter { x: tx, y: ty }
+            }
+            1 => Feature::Trap { turns: reader.u8()? },
+            2 => Feature::Pit,
+            _ => return Err(FormatError::Invalid("feature")),
+        };
+        if !in_bounds((x, y)) {
+            return Err(FormatError::Invalid("feature"));
+        }
+        maze.grid[x][y].feature = Some(feature);
+    }
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let (x, y) = reader.position()?;
+        let terrain = terrain_from_id(reader.u8()?).ok_or(FormatError::Invalid("terrain"))?;
+        if !in_bounds((x, y)) {
+            return Err(FormatError::Invalid("terrain"));
+        }
+        maze.grid[x][y].terrain = terrain;
+    }
+    if !reader.bytes.is_empty() {
+        return Err(FormatError::Invalid("trailing data"));
+    }
+    maze.seed = seed;
+    maze.algorithm = algorithm;
+    maze.braid = braid;
+    maze.rough = rough;
+    maze.doors = doors;
+    maze.one_ways = one_ways;
+    maze.teleporters = teleporters;
+    maze.traps = traps;
+    maze.start_layout = start_layout;
+    maze.starts = starts;
+    maze.treasure = treasure;
+    Ok(maze)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::commitment::Opening;
+
+    fn sample() -> Maze {
+        let mut maze = Maze::new(7, 5);
+        maze.algorithm = Algorithm::GrowingTree(Selection::Mixed(30));
+        maze.braid = 0.25;
+        maze.doors = 2;
+        maze.start_layout = StartLayout::PerPlayer(3);
+        maze.generate_with_seed(77);
+        maze
+    }
+
+    fn with_features() -> Maze {
+        let mut maze = sample();
+        maze.one_ways = 2;
+        maze.teleporters = 1;
+        maze.traps = 3;
+        maze.rough = 0.4;
+        maze.generate_with_seed(77);
+        maze
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let maze = sample();
+        let bytes = maze.to_bytes();
+        // Fixed header of 71 bytes, three starts, the 6 * 5 + 7 * 4 = 58 shared walls, then two
+        // doors and their keys, and empty one-way, feature and terrain sections.
+        assert_eq!(bytes.len(), 71 + 3 * 8 + 8 + (4 + 2 * 10) + (4 + 2 * 9) + 4 + 4 + 4);
+
+        let decoded = Maze::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.grid, maze.grid);
+        assert_eq!(decoded.seed, Some(77));
+        assert_eq!(decoded.algorithm, maze.algorithm);
+        assert_eq!(decoded.braid, maze.braid);
+        assert_eq!(decoded.doors, 2);
+        assert_eq!(decoded.start_layout, maze.start_layout);
+        assert_eq!(decoded.starts, maze.starts);
+        assert_eq!(decoded.treasure, maze.treasure);
+        assert!(decoded.validate().is_empty());
+
+        let path = std::env::temp_dir().join(format!("braid-format-test-{}.maze", std::process::id()));
+        maze.save(&path).unwrap();
+        let loaded = Maze::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.grid, maze.grid);
+    }
+
+    #[test]
+    fn test_rejects_malformed_data() {
+        let bytes = sample().to_bytes();
+        assert!(matches!(Maze::from_bytes(b"nope"), Err(FormatError::BadMagic)));
+
+        let mut future = bytes.clone();
+        future[4] = FORMAT_VERSION + 1;
+        assert!(matches!(Maze::from_bytes(&future), Err(FormatError::UnsupportedVersion(2))));
+
+        assert!(matches!(Maze::from_bytes(&bytes[..bytes.len() - 1]), Err(FormatError::Truncated)));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(Maze::from_bytes(&trailing), Err(FormatError::Invalid(_))));
+
+        let mut topology = bytes.clone();
+        topology[5] = 3;
+        assert!(matches!(Maze::from_bytes(&topology), Err(FormatError::Invalid("topology"))));
+    }
+
+    #[test]
+    fn test_rejects_huge_dimensions_without_the_data() {
+        // A header claiming a maze of 2^32-1 x 2^32-1 cells, with nothing like its walls behind it.
+        let mut huge = sample().to_bytes();
+        huge[6..14].copy_from_slice(&[0xff; 8]);
+        huge.truncate(71 + 3 * 8 + 8);
+        assert!(matches!(Maze::from_bytes(&huge), Err(FormatError::Truncated)));
+
+        // Nor does a huge start count make room for starts that are not there.
+        let mut starts = huge[..71].to_vec();
+        starts[67..71].copy_from_slice(&[0xff; 4]);
+        assert!(matches!(Maze::from_bytes(&starts), Err(FormatError::Truncated)));
+    }
+
+    #[test]
+    fn test_round_trip_features() {
+        let opening = Opening { nonce: [7; 32] };
+        let maze = with_features();
+        let decoded = Maze::from_bytes(&maze.to_bytes()).unwrap();
+        assert!(decoded.grid.iter().flatten().any(|cell| cell.feature.is_some()));
+        assert!(decoded.grid.iter().flatten().any(|cell| cell.terrain != Terrain::Corridor));
+        assert_eq!(decoded.grid, maze.grid);
+        assert_eq!((decoded.one_ways, decoded.teleporters, decoded.traps), (2, 1, 3));
+        assert_eq!(decoded.rough, 0.4);
+        assert_eq!(decoded.layout_commitment(&opening), maze.layout_commitment(&opening));
+    }
+
+    #[test]
+    fn test_round_trip_other_topologies() {
+        for topology in [Topology::Hex, Topology::Triangle] {
+            let mut maze = Maze::new(6, 5);
+            maze.topology = topology;
+            maze.braid = 0.5;
+            maze.generate_with_seed(12);
+            let decoded = Maze::from_bytes(&maze.to_bytes()).unwrap();
+            assert_eq!(decoded.topology, topology);
+            assert_eq!(decoded.grid, maze.grid);
+            assert!(decoded.validate().is_empty());
+        }
+    }
+
+}
@@ -0,0 +1,437 @@
+use rand::{Rng, RngCore};
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::Maze;
+
+/*
+ * - MazeGenerator: Carves passages into a maze whose cells start unvisited with all walls intact.
+ * - Algorithm: Per-game selection of a generator, serializable so it can be part of a game's config.
+ * - Prim: Randomized Prim's. Grows from random frontier cells, giving short, branchy dead ends.
+ * - Kruskal: Randomized Kruskal's. Merges random edges between disjoint sets, similar texture to Prim's.
+ * - RecursiveBacktracker: Depth-first walk, giving long winding corridors with few dead ends.
+ * - Wilson: Loop-erased random walks. Samples uniformly from all spanning trees, so it is unbiased.
+ * - Eller: Row by row set merging, needing only a single row of state.
+ * - GrowingTree: Generalizes the backtracker and Prim's through its cell selection strategy.
+ */
+
+// A maze generation algorithm. Implementations carve a perfect maze (exactly one path between any
+// two cells) and mark every cell as visited. All randomness must come from the given RNG so that
+// seeded generation stays reproducible.
+pub trait MazeGenerator {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore);
+}
+
+// Cell selection strategy for the growing tree algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Selection {
+    Newest,                     // Behaves like the recursive backtracker.
+    Oldest,                     // Long straight corridors radiating from the first cell.
+    Random,                     // Behaves like Prim's.
+    Mixed(u8),                  // Percentage chance of picking the newest cell, otherwise random.
+}
+
+// The generation algorithms a dungeon can offer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    Prim,
+    Kruskal,
+    RecursiveBacktracker,
+    Wilson,
+    Eller,
+    GrowingTree(Selection),
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::GrowingTree(Selection::Oldest)
+    }
+}
+
+impl Algorithm {
+    // Get the generator implementing this algorithm.
+    pub fn generator(&self) -> Box<dyn MazeGenerator> {
+        match *self {
+            Algorithm::Prim => Box::new(Prim),
+            Algorithm::Kruskal => Box::new(Kruskal),
+            Algorithm::RecursiveBacktracker => Box::new(RecursiveBacktracker),
+            Algorithm::Wilson => Box::new(Wilson),
+            Algorithm::Eller => Box::new(Eller),
+            Algorithm::GrowingTree(selection) => Box::new(GrowingTree { selection }),
+        }
+    }
+}
+
+// Pick a uniformly random cell of the maze.
+fn random_cell(maze: &Maze, rng: &mut dyn RngCore) -> (usize, usize) {
+    (rng.gen_range(0..maze.width), rng.gen_range(0..maze.height))
+}
+
+pub struct Prim;
+
+impl MazeGenerator for Prim {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        let mut in_frontier = vec![vec![false; maze.height]; maze.width];
+        let mut frontier = vec![];
+
+        let (sx, sy) = random_cell(maze, rng);
+        maze.grid[sx][sy].visited = true;
+        for (nx, ny) in maze.get_unvisited_neighbors(sx, sy) {
+            in_frontier[nx][ny] = true;
+            frontier.push((nx, ny));
+        }
+
+        while !frontier.is_empty() {
+            // Connect a random frontier cell to a random cell already in the maze.
+            let (cx, cy) = frontier.swap_remove(rng.gen_range(0..frontier.len()));
+            let visited: Vec<(usize, usize)> = maze.neighbors(cx, cy)
+                .into_iter()
+                .filter(|&(nx, ny)| maze.grid[nx][ny].visited)
+                .collect();
+            let &(px, py) = visited.choose(rng).unwrap();
+            maze.remove_wall(cx, cy, px, py);
+            maze.grid[cx][cy].visited = true;
+
+            for (nx, ny) in maze.get_unvisited_neighbors(cx, cy) {
+                if !in_frontier[nx][ny] {
+                    in_frontier[nx][ny] = true;
+                    frontier.push((nx, ny));
+                }
+            }
+        }
+    }
+}
+
+// Disjoint set forest over cell indices, used by Kruskal's algorithm.
+struct DisjointSets {
+    parent: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(size: usize) -> Self {
+        DisjointSets { parent: (0..size).collect() }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]]; // Path halving.
+            i = self.parent[i];
+        }
+        i
+    }
+
+    // Merge the sets containing a and b. Returns false if they were already the same set.
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra == rb {
+            return false;
+        }
+        self.parent[rb] = ra;
+        true
+    }
+}
+
+pub struct Kruskal;
+
+impl MazeGenerator for Kruskal {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        // Take each edge from the cell that comes first in grid order, so it is only added once.
+        let mut edges = vec![];
+        for x in 0..maze.width {
+            for y in 0..maze.height {
+                for (nx, ny) in maze.neighbors(x, y) {
+                    if (nx, ny) > (x, y) {
+                        edges.push(((x, y), (nx, ny)));
+                    }
+                }
+            }
+        }
+        edges.shuffle(rng);
+
+        let mut sets = DisjointSets::new(maze.width * maze.height);
+        for ((x1, y1), (x2, y2)) in edges {
+            if sets.union(x1 * maze.height + y1, x2 * maze.height + y2) {
+                maze.remove_wall(x1, y1, x2, y2);
+            }
+        }
+
+        for cell in maze.grid.iter_mut().flatten() {
+            cell.visited = true;
+        }
+    }
+}
+
+pub struct RecursiveBacktracker;
+
+impl MazeGenerator for RecursiveBacktracker {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        // An explicit stack avoids overflowing the call stack on large mazes.
+        let (sx, sy) = random_cell(maze, rng);
+        maze.grid[sx][sy].visited = true;
+        let mut stack = vec![(sx, sy)];
+
+        while let Some(&(cx, cy)) = stack.last() {
+            let neighbors = maze.get_unvisited_neighbors(cx, cy);
+            match neighbors.choose(rng) {
+                Some(&(nx, ny)) => {
+                    maze.remove_wall(cx, cy, nx, ny);
+                    maze.grid[nx][ny].visited = true;
+                    stack.push((nx, ny));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+    }
+}
+
+pub struct Wilson;
+
+impl MazeGenerator for Wilson {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        // The visited flag marks cells that are part of the tree.
+        let (sx, sy) = random_cell(maze, rng);
+        maze.grid[sx][sy].visited = true;
+
+        let mut cells = vec![];
+        for x in 0..maze.width {
+            for y in 0..maze.height {
+                cells.push((x, y));
+            }
+        }
+        cells.shuffle(rng);
+
+        // The direction each cell was last left in during the current walk. Overwriting it when
+        // a walk revisits a cell is what erases the loops.
+        let mut next = vec![vec![None; maze.height]; maze.width];
+        for (wx, wy) in cells {
+            if maze.grid[wx][wy].visited {
+                continue;
+            }
+
+            // Random walk until the walk hits the tree.
+            let (mut cx, mut cy) = (wx, wy);
+            while !maze.grid[cx][cy].visited {
+                let &(nx, ny) = maze.neighbors(cx, cy).choose(rng).unwrap();
+                next[cx][cy] = Some((nx, ny));
+                (cx, cy) = (nx, ny);
+            }
+
+            // Retrace the loop-erased walk, adding it to the tree.
+            let (mut cx, mut cy) = (wx, wy);
+            while !maze.grid[cx][cy].visited {
+                let (nx, ny) = next[cx][cy].unwrap();
+                maze.remove_wall(cx, cy, nx, ny);
+                maze.grid[cx][cy].visited = true;
+                (cx, cy) = (nx, ny);
+            }
+        }
+    }
+}
+
+pub struct Eller;
+
+impl Eller {
+    // Get the columns of the cells in the next row that a cell borders. Square cells have one,
+    // hexagons up to two and triangles pointing down none.
+    fn below(maze: &Maze, x: usize, y: usize) -> Vec<usize> {
+        maze.neighbors(x, y)
+            .into_iter()
+            .filter(|&(_, ny)| ny == y + 1)
+            .map(|(nx, _)| nx)
+            .collect()
+    }
+
+    // Relabel every cell of set b as set a.
+    fn merge(sets: &mut [Option<usize>], a: Option<usize>, b: Option<usize>) {
+        for set in sets.iter_mut() {
+            if *set == b {
+                *set = a;
+            }
+        }
+    }
+}
+
+impl MazeGenerator for Eller {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        let mut sets: Vec<Option<usize>> = vec![None; maze.width];
+        let mut next_set = 0;
+
+        for y in 0..maze.height {
+            let last_row = y + 1 == maze.height;
+
+            // Cells not joined from the row above start in their own set.
+            for set in sets.iter_mut() {
+                if set.is_none() {
+                    *set = Some(next_set);
+                    next_set += 1;
+                }
+            }
+
+            // Randomly join adjacent cells of different sets. The last row joins all of them.
+            for x in 0..maze.width.saturating_sub(1) {
+                let (a, b) = (sets[x], sets[x + 1]);
+                if a != b && (last_row || rng.gen_bool(0.5)) {
+                    maze.remove_wall(x, y, x + 1, y);
+                    Eller::merge(&mut sets, a, b);
+                }
+            }
+
+            for x in 0..maze.width {
+                maze.grid[x][y].visited = true;
+            }
+            if last_row {
+                break;
+            }
+
+            // A set with no cell bordering the next row would be cut off, so join it to the set
+            // beside it first. This only happens with triangles.
+            for x in 0..maze.width {
+                let id = sets[x];
+                let stranded = (0..maze.width).all(|m| sets[m] != id || Eller::below(maze, m, y).is_empty());
+                if stranded && x + 1 < maze.width && sets[x + 1] != id {
+                    maze.remove_wall(x, y, x + 1, y);
+                    let other = sets[x + 1];
+                    Eller::merge(&mut sets, other, id);
+                } else if stranded && x > 0 && sets[x - 1] != id {
+                    maze.remove_wall(x, y, x - 1, y);
+                    let other = sets[x - 1];
+                    Eller::merge(&mut sets, other, id);
+                }
+            }
+
+            // Every set continues downwards at least once, so no set is cut off. A cell below is
+            // only ever entered once, since a second entry from the same set would close a loop.
+            let mut below = vec![None; maze.width];
+            let mut ids: Vec<usize> = sets.iter().flatten().copied().collect();
+            ids.sort_unstable();
+            ids.dedup();
+            for id in ids {
+                let mut links: Vec<(usize, usize)> = (0..maze.width)
+                    .filter(|&x| sets[x] == Some(id))
+                    .flat_map(|x| Eller::below(maze, x, y).into_iter().map(move |bx| (x, bx)))
+                    .collect();
+                links.shuffle(rng);
+                let mut linked = false;
+                for &(x, bx) in &links {
+                    if below[bx].is_none() && (!linked || rng.gen_bool(0.5)) {
+                        maze.remove_wall(x, y, bx, y + 1);
+                        below[bx] = Some(id);
+                        linked = true;
+                    }
+                }
+                // Hexagons can find every cell below already taken by other sets. Joining one of
+                // them merges the two sets, which cannot close a loop.
+                if let (false, Some(&(x, bx))) = (linked, links.first()) {
+                    maze.remove_wall(x, y, bx, y + 1);
+                    let other = below[bx];
+                    Eller::merge(&mut below, Some(id), other);
+                }
+            }
+            sets = below;
+        }
+    }
+}
+
+pub struct GrowingTree {
+    pub selection: Selection,
+}
+
+impl GrowingTree {
+    // Pick the index of the next active cell to grow from.
+    fn select(&self, len: usize, rng: &mut dyn RngCore) -> usize {
+        match self.selection {
+            Selection::Newest => len - 1,
+            Selection::Oldest => 0,
+            Selection::Random => rng.gen_range(0..len),
+            Selection::Mixed(newest) => {
+                if rng.gen_range(0..100) < newest {
+                    len - 1
+                } else {
+                    rng.gen_range(0..len)
+                }
+            }
+        }
+    }
+}
+
+impl MazeGenerator for GrowingTree {
+    fn carve(&self, maze: &mut Maze, rng: &mut dyn RngCore) {
+        let (sx, sy) = random_cell(maze, rng);
+        maze.grid[sx][sy].visited = true;
+        let mut active = vec![(sx, sy)];
+
+        while !active.is_empty() {
+            let index = self.select(active.len(), rng);
+            let (cx, cy) = active[index];
+            let neighbors = maze.get_unvisited_neighbors(cx, cy);
+            match neighbors.choose(rng) {
+                Some(&(nx, ny)) => {
+                    maze.remove_wall(cx, cy, nx, ny);
+                    maze.grid[nx][ny].visited = true;
+                    active.push((nx, ny));
+                }
+                None => {
+                    // The cell is exhausted. Keep the order so Newest and Oldest stay meaningful.
+                    active.remove(index);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::topology::Topology;
+
+    const ALGORITHMS: [Algorithm; 9] = [
+        Algorithm::Prim,
+        Algorithm::Kruskal,
+        Algorithm::RecursiveBacktracker,
+        Algorithm::Wilson,
+        Algorithm::Eller,
+        Algorithm::GrowingTree(Selection::Newest),
+        Algorithm::GrowingTree(Selection::Oldest),
+        Algorithm::GrowingTree(Selection::Random),
+        Algorithm::GrowingTree(Selection::Mixed(50)),
+    ];
+
+    // Count the passages of a maze and the cells reachable from (0, 0).
+    fn passages_and_reachable(maze: &Maze) -> (usize, usize) {
+        // Every passage opens a wall on both of its cells.
+        let openings: usize = maze.grid.iter().flatten()
+            .map(|cell| cell.walls.iter().filter(|&&wall| !wall).count())
+            .sum();
+        let reachable = maze.reachable_from((0, 0)).iter().flatten().filter(|&&r| r).count();
+        (openings / 2, reachable)
+    }
+
+    #[test]
+    fn test_algorithms_generate_perfect_mazes() {
+        for topology in [Topology::Square, Topology::Hex, Topology::Triangle] {
+            for algorithm in ALGORITHMS {
+                let mut maze = Maze::new(9, 7);
+                maze.topology = topology;
+                maze.algorithm = algorithm;
+                maze.generate_with_seed(3);
+                assert!(maze.grid.iter().flatten().all(|cell| cell.visited), "{:?} {:?}", topology, algorithm);
+                // A spanning tree over n cells has exactly n - 1 passages.
+                assert_eq!(passages_and_reachable(&maze), (9 * 7 - 1, 9 * 7), "{:?} {:?}", topology, algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_algorithms_are_reproducible() {
+        for algorithm in ALGORITHMS {
+            let mut first = Maze::new(6, 6);
+            let mut second = Maze::new(6, 6);
+            first.algorithm = algorithm;
+            second.algorithm = algorithm;
+            first.generate_with_seed(11);
+            second.generate_with_seed(11);
+            assert_eq!(first.grid, second.grid, "{:?}", algorithm);
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use rand::{RngCore, SeedableRng};
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::{key_set, KeySet, Maze, MazeRng, Violation, MAX_KEYS};
+use crate::dungeon::render::{self, Overlay};
+use crate::dungeon::visibility::Visibility;
+use crate::dungeon::commitment::{self, Commitment, Domain, Encode, Opening};
+
+/*
+ * - Layered Maze: Floors of the same size stacked on top of each other, floor 0 at the top. Each
+ *   floor is an ordinary Maze with its own algorithm, topology and braiding.
+ * - Stairs: Passages from a cell down to the cell directly below it on the next floor.
+ * - Generate: Carves every floor, then links each pair of adjacent floors with stairs. Players
+ *   enter on the top floor and the treasure lies at the center of the deepest floor. Each floor's
+ *   starts and treasure are where players arrive on it and leave it, which is what its doors and
+ *   features are placed against. Key ids are unique across floors.
+ * - Moves: A player moves one step at a time, through a side of their cell or up or down stairs.
+ *   A step is only allowed through an open side, with the key to any door on it, and not against
+ *   a one-way passage.
+ * - Masked View: Exploration masks are indexed [z][x][y], one ordinary mask per floor, so the
+ *   masked-view model of a single maze carries over floor by floor.
+ * - Visible Cells: What a player can see from the cells they explored, by the game's visibility
+ *   rule. Sight stays on the floor the player is on.
+ * - Commitments: Hiding commitments to the layout, covering every floor and the stairs, and to
+ *   the solution path (see dungeon::commitment).
+ */
+
+// A cell of a layered maze, as (x, y, z) with z counting floors down from the top.
+pub type Position = (usize, usize, usize);
+
+// A single move a player can make from their cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Side(usize),    // Through a side of the cell, numbered as by the floor's topology.
+    Up,             // Up the stairs ending in the cell.
+    Down,           // Down the stairs starting in the cell.
+}
+
+// Representation of a maze spread over several floors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayeredMaze {
+    pub width: usize,           // Width of every floor.
+    pub height: usize,          // Height of every floor.
+    pub depth: usize,           // Number of floors.
+    pub floors: Vec<Maze>,      // Floors from the top down. Configure them before generating, up to MAX_KEYS doors in total.
+    pub stairs: Vec<Position>,  // Stairs from (x, y, z) down to (x, y, z + 1), sorted.
+    pub stairs_per_floor: usize, // Stairs generated between each pair of adjacent floors.
+    pub seed: Option<u64>,      // Seed the maze was generated from, if it was seeded.
+    pub starts: Vec<Position>,  // Start cells on the top floor, placed by its start layout.
+    pub treasure: Position,     // Cell holding the treasure, at the center of the deepest floor.
+}
+
+// The full layout of the maze, as commit_layout covers it.
+impl Encode for LayeredMaze {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.width, self.height, self.depth).encode(out);
+        self.floors.encode(out);
+        self.stairs.encode(out);
+        self.starts.encode(out);
+        self.treasure.encode(out);
+    }
+}
+
+impl LayeredMaze {
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        LayeredMaze {
+            width,
+            height,
+            depth,
+            floors: (0..depth).map(|_| Maze::new(width, height)).collect(),
+            stairs: vec![],
+            stairs_per_floor: 2,
+            seed: None,
+            starts: vec![],
+            treasure: (width / 2, height / 2, depth.saturating_sub(1)),
+        }
+    }
+
+    // Create and generate a layered maze whose layout is fully determined by the seed.
+    pub fn from_seed(width: usize, height: usize, depth: usize, seed: u64) -> Self {
+        let mut maze = LayeredMaze::new(width, height, depth);
+        maze.generate_with_seed(seed);
+        maze
+    }
+
+    // Generate the maze from fresh entropy. The result cannot be rebuilt later.
+    pub fn generate(&mut self) {
+        let mut rng = rand::thread_rng();
+        self.generate_with_rng(&mut rng);
+    }
+
+    // Generate the maze deterministically from a seed, recording the seed.
+    pub fn generate_with_seed(&mut self, seed: u64) {
+        let mut rng = MazeRng::seed_from_u64(seed);
+        self.generate_with_rng(&mut rng);
+        self.seed = Some(seed);
+    }
+
+    // Generate every floor and the stairs between them, drawing all randomness from the RNG.
+    pub fn generate_with_rng<R: RngCore>(&mut self, rng: &mut R) {
+        self.seed = None;
+        // Generation leaves every cell of a floor connected to the rest of that floor, so a
+        // single staircase between each pair of floors already connects the whole maze. Doors
+        // and features wait until the stairs are known.
+        for floor in self.floors.iter_mut() {
+            floor.generate_passages(rng);
+        }
+
+        self.stairs.clear();
+        let mut cells: Vec<(usize, usize)> = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .collect();
+        for z in 0..self.depth.saturating_sub(1) {
+            let (chosen, _) = cells.partial_shuffle(rng, self.stairs_per_floor.max(1));
+            self.stairs.extend(chosen.iter().map(|&(x, y)| (x, y, z)));
+        }
+        self.stairs.sort_unstable();
+        self.starts = self.floors[0].starts.iter().map(|&(x, y)| (x, y, 0)).collect();
+
+        // Place each floor's features and doors against the stairs players arrive by and the
+        // stairs, or the treasure, they are heading for.
+        let mut next_key = 0;
+        for z in 0..self.depth {
+            let on_floor = |floor: usize| self.stairs.iter().filter(move |s| s.2 == floor).map(|&(x, y, _)| (x, y));
+            let arrivals: Vec<(usize, usize)> = if z == 0 { self.floors[0].starts.clone() } else { on_floor(z - 1).collect() };
+            let exit = if z + 1 == self.depth { (self.treasure.0, self.treasure.1) } else { on_floor(z).next().unwrap() };
+            let floor = &mut self.floors[z];
+            floor.starts = arrivals;
+            floor.treasure = exit;
+            floor.place_features(rng);
+            next_key += floor.place_doors(next_key as u8, floor.doors.min(MAX_KEYS - next_key), rng);
+        }
+    }
+
+    // Get the key lying in a cell as a key set.
+    pub fn keys_at(&self, (x, y, z): Position) -> KeySet {
+        self.floors[z].keys_at(x, y)
+    }
+
+    // Get the cells a player holding the given keys can step into, along the floor or by stairs,
+    // each paired with where they end up, as for Maze::landing. Stairs never lead onto a
+    // feature's effect, only walking into its cell does.
+    pub fn steps(&self, (x, y, z): Position, keys: KeySet) -> Vec<(Position, Option<Position>)> {
+        let floor = &self.floors[z];
+        let mut steps: Vec<(Position, Option<Position>)> = floor.passable_neighbors(x, y, keys)
+            .into_iter()
+            .map(|(nx, ny)| ((nx, ny, z), floor.landing(nx, ny).map(|(lx, ly)| (lx, ly, z))))
+            .collect();
+        steps.extend(self.stairs_from((x, y, z)).into_iter().map(|cell| (cell, Some(cell))));
+        steps
+    }
+
+    // Get the cell a player holding the given keys steps into by moving in a direction, paired with
+    // where they end up, as for steps. None if the move is blocked.
+    pub fn step(&self, (x, y, z): Position, direction: Direction, keys: KeySet) -> Option<(Position, Option<Position>)> {
+        match direction {
+            Direction::Side(side) => {
+                let floor = &self.floors[z];
+                let (nx, ny) = floor.topology.neighbor(x, y, side, self.width, self.height)?;
+                if !floor.can_pass(x, y, nx, ny, keys) {
+                    return None;
+                }
+                Some(((nx, ny, z), floor.landing(nx, ny).map(|(lx, ly)| (lx, ly, z))))
+            }
+            Direction::Up => (z > 0 && self.has_stairs_down((x, y, z - 1))).then(|| ((x, y, z - 1), Some((x, y, z - 1)))),
+            Direction::Down => (z + 1 < self.depth && self.has_stairs_down((x, y, z))).then(|| ((x, y, z + 1), Some((x, y, z + 1)))),
+        }
+    }
+
+    // Get the cells a player holding the given keys can end up in with a single step.
+    pub fn moves(&self, position: Position, keys: KeySet) -> Vec<Position> {
+        self.steps(position, keys).into_iter().filter_map(|(_, landed)| landed).collect()
+    }
+
+    // Get the cells reachable by taking the stairs in a cell, up or down.
+    fn stairs_from(&self, (x, y, z): Position) -> Vec<Position> {
+        let mut cells = vec![];
+        if z > 0 && self.has_stairs_down((x, y, z - 1)) {
+            cells.push((x, y, z - 1)); // Up the stairs.
+        }
+        if z + 1 < self.depth && self.has_stairs_down((x, y, z)) {
+            cells.push((x, y, z + 1)); // Down the stairs.
+        }
+        cells
+    }
+
+    // Get which cells, indexed [z][x][y], a player starting from a cell with the given keys can
+    // reach, picking up every key on the way, together with all the keys they end up holding.
+    pub fn reachable_with_keys(&self, start: Position, mut keys: KeySet) -> (Vec<Vec<Vec<bool>>>, KeySet) {
+        keys |= self.keys_at(start);
+        loop {
+            let mut reachable = vec![vec![vec![false; self.height]; self.width]; self.depth];
+            let mut stack = vec![start];
+            reachable[start.2][start.0][start.1] = true;
+            let mut held = keys;
+            while let Some(cell) = stack.pop() {
+                for (nx, ny, nz) in self.moves(cell, held) {
+                    if !reachable[nz][nx][ny] {
+                        reachable[nz][nx][ny] = true;
+                        held |= self.keys_at((nx, ny, nz));
+                        stack.push((nx, ny, nz));
+                    }
+                }
+            }
+            // Search again with any keys picked up late, until no new keys turn up.
+            if held == keys {
+                return (reachable, keys);
+            }
+            keys = held;
+        }
+    }
+
+    // Get the turns a path takes, as for Maze::path_cost, with a flight of stairs costing the
+    // cell it leads to.
+    pub fn path_cost(&self, path: &[Position]) -> usize {
+        path.windows(2)
+            .map(|step| {
+                let ((x1, y1, z1), (x2, y2, z2)) = (step[0], step[1]);
+                if z1 == z2 {
+                    self.floors[z2].path_cost(&[(x1, y1), (x2, y2)])
+                } else {
+                    self.floors[z2].step_cost(x2, y2)
+                }
+            })
+            .sum()
+    }
+
+    // Get which cells, indexed [z][x][y], a player can see from the cells of an exploration mask.
+    pub fn visible_cells(&self, explored: &[Vec<Vec<bool>>], visibility: Visibility) -> Vec<Vec<Vec<bool>>> {
+        let mut visible = vec![vec![vec![false; self.height]; self.width]; self.depth];
+        for ((floor, explored), visible) in self.floors.iter().zip(explored).zip(visible.iter_mut()) {
+            let cells = (0..self.width).flat_map(|x| (0..self.height).map(move |y| (x, y)));
+            for (x, y) in cells.filter(|&(x, y)| explored[x][y]) {
+                let seen = visibility.visible_cells(floor, (x, y));
+                for (column, seen) in visible.iter_mut().zip(seen) {
+                    for (cell, seen) in column.iter_mut().zip(seen) {
+                        *cell |= seen;
+                    }
+                }
+            }
+        }
+        visible
+    }
+
+    // Check whether there are stairs from a cell down to the floor below.
+    pub fn has_stairs_down(&self, (x, y, z): Position) -> bool {
+        self.stairs.binary_search(&(x, y, z)).is_ok()
+    }
+
+    // Get the cells that can be reached from a cell in one step, along its floor or by stairs,
+    // regardless of doors.
+    pub fn open_neighbors(&self, (x, y, z): Position) -> Vec<Position> {
+        let mut neighbors: Vec<Position> = self.floors[z].open_neighbors(x, y)
+            .into_iter()
+            .map(|(nx, ny)| (nx, ny, z))
+            .collect();
+        neighbors.extend(self.stairs_from((x, y, z)));
+        neighbors
+    }
+
+    // Get which cells, indexed [z][x][y], can be reached from a cell.
+    pub fn reachable_from(&self, (x, y, z): Position) -> Vec<Vec<Vec<bool>>> {
+        let mut reachable = vec![vec![vec![false; self.height]; self.width]; self.depth];
+        let mut stack = vec![(x, y, z)];
+        reachable[z][x][y] = true;
+        while let Some(cell) = stack.pop() {
+            for (nx, ny, nz) in self.open_neighbors(cell) {
+                if !reachable[nz][nx][ny] {
+                    reachable[nz][nx][ny] = true;
+                    stack.push((nx, ny, nz));
+                }
+            }
+        }
+        reachable
+    }
+
+    // Find a cheapest path from a start cell to the treasure, taking stairs as single steps that
+    // cost the cell they lead to. Like the solvers for a single floor, this is Dijkstra's
+    // algorithm over (cell, keys held) states.
+    pub fn solution_path(&self, start: Position) -> Option<Vec<Position>> {
+        let first = (start, self.keys_at(start));
+        let mut parents: HashMap<(Position, KeySet), (Position, KeySet)> = HashMap::new();
+        let mut costs = HashMap::from([(first, 0)]);
+        let mut open = BinaryHeap::from([Reverse((0, first))]);
+
+        while let Some(Reverse((cost, state @ (cell, keys)))) = open.pop() {
+            if cell == self.treasure {
+                let mut path = vec![cell];
+                let mut current = state;
+                while let Some(&parent) = parents.get(&current) {
+                    path.push(parent.0);
+                    current = parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if cost > costs[&state] {
+                continue; // A cheaper way here was already expanded.
+            }
+            for ((ex, ey, ez), landed) in self.steps(cell, keys) {
+                let Some(next) = landed else { continue };
+                let next = (next, keys | self.keys_at(next));
+                let cost = cost + self.floors[ez].step_cost(ex, ey);
+                if costs.get(&next).is_none_or(|&known| cost < known) {
+                    costs.insert(next, cost);
+                    parents.insert(next, state);
+                    open.push(Reverse((cost, next)));
+                }
+            }
+        }
+        None
+    }
+
+    // Check the structure of every floor and the stairs between them, returning each violation
+    // found together with the floor it was found on.
+    pub fn validate(&self) -> Vec<(usize, Violation)> {
+        let mut violations = vec![];
+        let size = Violation::GridSize { width: self.width, height: self.height };
+        if self.depth == 0 || self.floors.len() != self.depth {
+            violations.push((0, size));
+            return violations;
+        }
+        for (z, floor) in self.floors.iter().enumerate() {
+            if (floor.width, floor.height) != (self.width, self.height) {
+                violations.push((z, size.clone()));
+                continue;
+            }
+            // A floor only has to be connected through the rest of the maze, and may rely on keys
+            // from other floors, so only its walls and doors are checked here.
+            violations.extend(floor.validate().into_iter()
+                .filter(|violation| matches!(violation,
+                    Violation::GridSize { .. }
+                    | Violation::CellPosition { .. }
+                    | Violation::InconsistentWall { .. }
+                    | Violation::MissingBoundaryWall { .. }
+                    | Violation::InconsistentDoor { .. }
+                    | Violation::MisplacedDoor { .. }
+                    | Violation::MisplacedOneWay { .. }
+                    | Violation::BrokenTeleporter { .. }
+                    | Violation::InvalidKey { .. }))
+                .map(|violation| (z, violation)));
+        }
+        if !violations.is_empty() {
+            return violations;
+        }
+
+        let in_bounds = |&(x, y, z): &Position| x < self.width && y < self.height && z < self.depth;
+        for &(x, y, z) in &self.stairs {
+            // Stairs must lead to a floor that exists.
+            if !in_bounds(&(x, y, z + 1)) {
+                violations.push((z, Violation::OutOfBounds { x, y }));
+            }
+        }
+        if self.starts.is_empty() {
+            violations.push((0, Violation::NoStarts));
+        }
+        for &(x, y, z) in self.starts.iter().chain(std::iter::once(&self.treasure)) {
+            if !in_bounds(&(x, y, z)) {
+                violations.push((z, Violation::OutOfBounds { x, y }));
+            }
+        }
+        if !violations.is_empty() {
+            return violations;
+        }
+
+        // Every door needs its key somewhere on one of the floors.
+        let cells = || self.floors.iter().enumerate().flat_map(|(z, floor)| floor.grid.iter().flatten().map(move |cell| (z, cell)));
+        let keys: KeySet = cells().filter_map(|(_, cell)| cell.key).fold(0, |keys, key| keys | key_set(key));
+        let mut missing: Vec<(usize, u8)> = cells()
+            .flat_map(|(z, cell)| cell.doors.iter().flatten().map(move |&key| (z, key)))
+            .filter(|&(_, key)| (key as usize) < MAX_KEYS && keys & key_set(key) == 0)
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        violations.extend(missing.into_iter().map(|(z, key)| (z, Violation::MissingKey { key })));
+
+        // Every cell on every floor must be connected to the treasure, and every connected start
+        // must be able to collect its way through the doors to it.
+        let reachable = self.reachable_from(self.treasure);
+        for (z, floor) in reachable.iter().enumerate() {
+            for (x, column) in floor.iter().enumerate() {
+                for (y, &r) in column.iter().enumerate() {
+                    if !r {
+                        violations.push((z, Violation::Unreachable { x, y }));
+                    }
+                }
+            }
+        }
+        let (tx, ty, tz) = self.treasure;
+        for &(x, y, z) in self.starts.iter().filter(|&&(x, y, z)| reachable[z][x][y]) {
+            if !self.reachable_with_keys((x, y, z), 0).0[tz][tx][ty] {
+                violations.push((z, Violation::Locked { x, y }));
+            }
+        }
+        violations
+    }
+
+    // Generate a masked view of the maze from per-floor masks indexed [z][x][y]. Stairs are only
+    // shown once the cell at either end has been explored.
+    pub fn get_masked_maze(&self, masks: &[Vec<Vec<bool>>]) -> LayeredMaze {
+        let floors = self.floors.iter()
+            .zip(masks)
+            .map(|(floor, mask)| floor.get_masked_maze(mask))
+            .collect();
+        let stairs = self.stairs.iter()
+            .copied()
+            .filter(|&(x, y, z)| masks[z][x][y] || masks[z + 1][x][y])
+            .collect();
+        LayeredMaze {
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            floors,
+            stairs,
+            stairs_per_floor: self.stairs_per_floor,
+            seed: None, // The seed would reveal the whole maze, so it is never part of a view.
+            starts: self.starts.clone(),
+            treasure: self.treasure,
+        }
+    }
+
+    // Commit to the full layout: the dimensions, every floor's layout, the stairs, starts and
+    // treasure. The opening stays with the dungeon until the layout is revealed.
+    pub fn commit_layout<R: RngCore>(&self, rng: &mut R) -> (Commitment, Opening) {
+        commitment::commit(Domain::LayeredLayout, self, rng)
+    }
+
+    // Get the commitment to the layout under a given opening, to check a revealed layout against.
+    pub fn layout_commitment(&self, opening: &Opening) -> Commitment {
+        commitment::commit_with(Domain::LayeredLayout, self, opening)
+    }
+
+    // Commit to a solution path through the floors, keeping the opening until it is revealed.
+    pub fn commit_solution_path<R: RngCore>(&self, solution_path: &[Position], rng: &mut R) -> (Commitment, Opening) {
+        commitment::commit(Domain::LayeredPath, solution_path, rng)
+    }
+
+    // Render every floor in ASCII from the top down, marking the cells with stairs.
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::new();
+        for (z, floor) in self.floors.iter().enumerate() {
+            let on_floor = |cells: &[Position]| -> Vec<(usize, usize)> {
+                cells.iter().filter(|cell| cell.2 == z).map(|&(x, y, _)| (x, y)).collect()
+            };
+            // Stairs are shown on both floors they join.
+            let mut stairs = on_floor(&self.stairs);
+            if z > 0 {
+                stairs.extend(on_floor(&self.stairs.iter().map(|&(x, y, sz)| (x, y, sz + 1)).collect::<Vec<_>>()));
+            }
+            let overlay = Overlay {
+                starts: on_floor(&self.starts),
+                treasure: on_floor(&[self.treasure]).first().copied(),
+                stairs,
+                ..Default::default()
+            };
+            out.push_str(&format!("Floor {}\n", z));
+            out.push_str(&render::render(floor, &overlay));
+        }
+        out
+    }
+
+    // Display every floor in ASCII.
+    pub fn display(&self) {
+        print!("{}", self.to_ascii());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_connects_floors() {
+        let opening = Opening { nonce: [7; 32] };
+        let maze = LayeredMaze::from_seed(6, 5, 3, 21);
+        assert_eq!(maze.treasure, (3, 2, 2));
+        assert_eq!(maze.starts, vec![(0, 0, 0)]);
+        assert_eq!(maze.stairs.len(), 2 * 2);
+        assert!(maze.validate().is_empty());
+
+        // The path has to go down both flights of stairs.
+        let path = maze.solution_path(maze.starts[0]).unwrap();
+        assert_eq!(path.last(), Some(&maze.treasure));
+        assert!(path.windows(2).filter(|step| step[0].2 != step[1].2).count() >= 2);
+
+        let rebuilt = LayeredMaze::from_seed(6, 5, 3, 21);
+        assert_eq!(rebuilt.stairs, maze.stairs);
+        assert_eq!(rebuilt.layout_commitment(&opening), maze.layout_commitment(&opening));
+
+        // Without stairs the deeper floors are cut off.
+        let mut cut = maze.clone();
+        cut.stairs.clear();
+        assert!(cut.solution_path(maze.starts[0]).is_none());
+        assert!(cut.validate().contains(&(0, Violation::Unreachable { x: 0, y: 0 })));
+        assert_ne!(cut.layout_commitment(&opening), maze.layout_commitment(&opening));
+    }
+
+    #[test]
+    fn test_doors_on_every_floor() {
+        let mut maze = LayeredMaze::new(8, 6, 3);
+        for floor in maze.floors.iter_mut() {
+            floor.doors = 2;
+        }
+        maze.generate_with_seed(8);
+        assert!(maze.validate().is_empty());
+
+        // Keys are numbered across floors, so no key opens a door on another floor.
+        let mut keys: Vec<u8> = maze.floors.iter()
+            .flat_map(|floor| floor.grid.iter().flatten().filter_map(|cell| cell.key))
+            .collect();
+        // A floor may fit fewer doors than asked for, when its exit lies close to where players arrive.
+        keys.sort_unstable();
+        assert!(!keys.is_empty() && keys.len() <= 6);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Each floor's keys are reachable from every staircase arriving on it.
+        for (z, floor) in maze.floors.iter().enumerate() {
+            assert_eq!(floor.starts.len(), if z == 0 { 1 } else { 2 });
+            assert!(floor.validate().is_empty());
+        }
+        assert!(maze.solution_path(maze.starts[0]).is_some());
+    }
+
+    #[test]
+    fn test_features_on_every_floor() {
+        let mut maze = LayeredMaze::new(8, 6, 2);
+        for floor in maze.floors.iter_mut() {
+            floor.one_ways = 3;
+            floor.teleporters = 1;
+            floor.traps = 3;
+            floor.rough = 0.3;
+        }
+        maze.generate_with_seed(13);
+        assert!(maze.validate().is_empty());
+        assert!(maze.solution_path(maze.starts[0]).is_some());
+    }
+
+    #[test]
+    fn test_steps_follow_walls_and_stairs() {
+        let maze = LayeredMaze::from_seed(6, 5, 2, 21);
+        let start = maze.starts[0];
+        // Every direction is either blocked or one of the steps out of the cell.
+        let mut stepped: Vec<_> = (0..4).map(Direction::Side)
+            .chain([Direction::Up, Direction::Down])
+            .filter_map(|direction| maze.step(start, direction, 0))
+            .collect();
+        let mut steps = maze.steps(start, 0);
+        stepped.sort_unstable();
+        steps.sort_unstable();
+        assert_eq!(stepped, steps);
+
+        let (x, y, z) = maze.stairs[0];
+        assert_eq!(maze.step((x, y, z), Direction::Down, 0), Some(((x, y, z + 1), Some((x, y, z + 1)))));
+        assert_eq!(maze.step((x, y, z + 1), Direction::Up, 0), Some(((x, y, z), Some((x, y, z)))));
+        assert_eq!(maze.step((x, y, z), Direction::Up, 0), None);
+        assert_eq!(maze.step(start, Direction::Side(4), 0), None);
+    }
+
+    #[test]
+    fn test_masked_view_per_floor() {
+        let maze = LayeredMaze::from_seed(4, 4, 2, 5);
+        let (x, y, _) = maze.stairs[0];
+        let mut masks = vec![vec![vec![false; 4]; 4]; 2];
+        masks[0][x][y] = true;
+        let view = maze.get_masked_maze(&masks);
+        assert_eq!(view.seed, None);
+        assert!(view.floors[0].grid[x][y].visited);
+        assert!(!view.floors[1].grid[x][y].visited);
+        // Only the stairs from the explored cell are shown.
+        assert_eq!(view.stairs, vec![(x, y, 0)]);
+        assert!(view.to_ascii().contains(" = "));
+    }
+
+    #[test]
+    fn test_visible_cells_stay_on_floor() {
+        let maze = LayeredMaze::from_seed(6, 5, 2, 17);
+        let (x, y, z) = maze.starts[0];
+        let mut explored = vec![vec![vec![false; 5]; 6]; 2];
+        explored[z][x][y] = true;
+
+        let here = maze.visible_cells(&explored, Visibility::CurrentCell);
+        assert_eq!(here, explored);
+        let sight = maze.visible_cells(&explored, Visibility::LineOfSight);
+        let wide = maze.visible_cells(&explored, Visibility::Radius(30));
+        assert!(sight[0][x][y] && sight[1].iter().flatten().all(|&seen| !seen));
+        // With a radius past the size of the floor, the whole of a perfect floor is in view.
+        assert!(wide[0].iter().flatten().all(|&seen| seen));
+        assert!(wide[1].iter().flatten().all(|&seen| !seen));
+        for (sight, wide) in sight[0].iter().flatten().zip(wide[0].iter().flatten()) {
+            assert!(!sight || *wide);
+        }
+    }
+}
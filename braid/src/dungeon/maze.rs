@@ -1,14 +1,102 @@
-use rand::Rng;
-use std::collections::VecDeque;
-use sha2::{Sha256, Digest}; // For cryptographic commitments.
+use rand::{Rng, RngCore, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha20Rng;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::dungeon::generator::Algorithm;
+use crate::dungeon::solver;
+use crate::dungeon::render::{self, Overlay};
+use crate::dungeon::format::{self, FormatError};
+use crate::dungeon::compact::CompactMaze;
+use crate::dungeon::commitment::{self, Commitment, Domain, Encode, Opening};
+use crate::dungeon::topology::{Topology, MAX_SIDES};
+
+// Seedable RNG used for reproducible maze generation. ChaCha20's output stream is fixed by its
+// specification, so a seed committed by a dungeon rebuilds the same grid on any verifier.
+pub type MazeRng = ChaCha20Rng;
+
+// Set of keys a player holds, with bit k set for key k.
+pub type KeySet = u32;
+
+// Most doors a maze can have, one per key a KeySet can hold.
+pub const MAX_KEYS: usize = 32;
+
+// Get the key set holding just the given key, or no keys for an id a KeySet cannot hold.
+pub fn key_set(key: u8) -> KeySet {
+    1u32.checked_shl(key as u32).unwrap_or(0)
+}
 
 // Representation of a single cell in the maze.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cell {
     pub x: usize,               // X coordinate of the cell.
     pub y: usize,               // Y coordinate of the cell.
     pub visited: bool,          // Whether the cell has been visited during maze generation.
-    pub walls: [bool; 4],       // Walls of the cell in the order [north, east, south, west].
+    pub walls: [bool; MAX_SIDES], // Walls of the cell, one per side of the maze's topology. Square cells use [north, east, south, west].
+    pub doors: [Option<u8>; MAX_SIDES], // Key needed to pass through each open side, if it is a locked door.
+    pub key: Option<u8>,        // Key lying in the cell, picked up by entering it.
+    pub one_way: [bool; MAX_SIDES], // Open sides that can only be passed leaving the cell, not entering it.
+    pub feature: Option<Feature>, // Teleporter or trap acting on a player entering the cell.
+    pub terrain: Terrain,       // Ground of the cell, setting the turns it takes to step into it.
+}
+
+// Ground covering a cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Terrain {
+    #[default]
+    Corridor,
+    Mud,
+    Water,
+    Rubble,
+}
+
+impl Terrain {
+    // Turns it takes to step into a cell of this terrain.
+    pub fn cost(&self) -> usize {
+        match self {
+            Terrain::Corridor => 1,
+            Terrain::Mud => 2,
+            Terrain::Water => 3,
+            Terrain::Rubble => 4,
+        }
+    }
+}
+
+impl Encode for Terrain {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Terrain::Corridor => 0,
+            Terrain::Mud => 1,
+            Terrain::Water => 2,
+            Terrain::Rubble => 3,
+        });
+    }
+}
+
+// Something in a cell that acts on a player stepping into it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Feature {
+    Teleporter { x: usize, y: usize }, // Moves the player on to the paired teleporter at (x, y).
+    Trap { turns: u8 },                 // Holds the player for extra turns.
+    Pit,                                // Drops the player back at their start.
+}
+
+impl Encode for Feature {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Feature::Teleporter { x, y } => {
+                out.push(0);
+                (x, y).encode(out);
+            }
+            Feature::Trap { turns } => {
+                out.push(1);
+                turns.encode(out);
+            }
+            Feature::Pit => out.push(2),
+        }
+    }
 }
 
 impl Cell {
@@ -16,17 +104,77 @@ impl Cell {
         Cell {
             x,
             y,
-            visited: false,     // Used in the initial generation (see Maze.generate).
-            walls: [true; 4],   // All walls are initially present.
+            visited: false,     // Used in the initial generation (see dungeon::generator).
+            walls: [true; MAX_SIDES], // All walls are initially present.
+            doors: [None; MAX_SIDES], // No doors, keys or features until generation places them.
+            key: None,
+            one_way: [false; MAX_SIDES],
+            feature: None,
+            terrain: Terrain::Corridor,
         }
     }
 }
 
+// Where players enter the maze.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StartLayout {
+    Shared,                     // Every player enters at the same corner cell.
+    PerPlayer(usize),           // Each player gets their own entrance, spread evenly around the boundary.
+}
+
+// A structural problem found by Maze::validate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    GridSize { width: usize, height: usize },       // The grid does not match the stated dimensions.
+    CellPosition { x: usize, y: usize },            // The cell at (x, y) records different coordinates.
+    InconsistentWall { x: usize, y: usize, wall: usize }, // The wall disagrees with the neighbor's matching wall.
+    MissingBoundaryWall { x: usize, y: usize, wall: usize }, // A wall on the outer edge of the maze is open.
+    OutOfBounds { x: usize, y: usize },             // A start or the treasure lies outside the maze.
+    NoStarts,                                       // The maze has no start cells.
+    Unreachable { x: usize, y: usize },             // The cell cannot be reached from the treasure.
+    InconsistentDoor { x: usize, y: usize, wall: usize }, // The door disagrees with the neighbor's matching door.
+    MisplacedDoor { x: usize, y: usize, wall: usize }, // A door sits in a wall rather than across a passage.
+    MissingKey { key: u8 },                         // No cell holds the key to a door.
+    Locked { x: usize, y: usize },                  // The start cannot reach the treasure, even collecting every key it can.
+    MisplacedOneWay { x: usize, y: usize, wall: usize }, // A one-way side is a wall, or the passage is one-way in both directions.
+    BrokenTeleporter { x: usize, y: usize },        // The teleporter does not lead to a teleporter leading back.
+    InvalidKey { x: usize, y: usize, key: u8 },     // A key or door in the cell has an id of MAX_KEYS or more.
+}
+
 // Representation of the maze.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Maze {
     pub width: usize,           // Width of the maze.
     pub height: usize,          // Height of the maze.
+    pub topology: Topology,     // Tiling the cells are laid out on.
     pub grid: Vec<Vec<Cell>>,   // 2D grid of cells.
+    pub seed: Option<u64>,      // Seed the maze was generated from, if it was seeded.
+    pub algorithm: Algorithm,   // Algorithm used to carve the maze.
+    pub braid: f64,             // Fraction of dead ends removed after carving (0.0 keeps the maze perfect).
+    pub rough: f64,             // Fraction of cells covered in mud, water or rubble rather than corridor.
+    pub doors: usize,           // Number of locked doors placed on the way to the treasure, at most MAX_KEYS.
+    pub one_ways: usize,        // Number of passages made one-way.
+    pub teleporters: usize,     // Number of teleporter pairs.
+    pub traps: usize,           // Number of trap cells, either holding players for turns or dropping them into a pit.
+    pub start_layout: StartLayout, // How start cells are assigned to players.
+    pub starts: Vec<(usize, usize)>, // Start cells, indexed by player for a per-player layout.
+    pub treasure: (usize, usize), // Cell holding the treasure, at the center of the maze.
+}
+
+// The full layout of the maze, as commit_layout covers it.
+impl Encode for Maze {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.width, self.height, self.topology).encode(out);
+        self.starts.encode(out);
+        self.treasure.encode(out);
+        self.grid.len().encode(out);
+        for column in &self.grid {
+            column.len().encode(out);
+            for cell in column {
+                out.extend(self.cell_layout(cell));
+            }
+        }
+    }
 }
 
 impl Maze {
@@ -40,85 +188,635 @@ impl Maze {
             }
             grid.push(row);
         }
-        Maze { width, height, grid }
+        Maze {
+            width,
+            height,
+            topology: Topology::default(),
+            grid,
+            seed: None,
+            algorithm: Algorithm::default(),
+            braid: 0.0,
+            rough: 0.0,
+            doors: 0,
+            one_ways: 0,
+            teleporters: 0,
+            traps: 0,
+            start_layout: StartLayout::Shared,
+            starts: vec![],
+            treasure: (width / 2, height / 2),
+        }
+    }
+
+    // Create and generate a maze whose layout is fully determined by the seed.
+    pub fn from_seed(width: usize, height: usize, seed: u64) -> Self {
+        let mut maze = Maze::new(width, height);
+        maze.generate_with_seed(seed);
+        maze
     }
 
-    // Generate the maze using Prim's Algorithm.
+    // Generate the maze from fresh entropy. The result cannot be rebuilt later.
     pub fn generate(&mut self) {
         let mut rng = rand::thread_rng();
-        let mut frontier = VecDeque::new();
+        self.generate_with_rng(&mut rng);
+    }
 
-        // Start from a random cell.
-        let start_x = rng.gen_range(0..self.width);
-        let start_y = rng.gen_range(0..self.height);
-        self.grid[start_x][start_y].visited = true;
-        frontier.push_back((start_x, start_y));
+    // Generate the maze deterministically from a seed, recording the seed so it can be
+    // committed to and handed to a verifier once the game is over.
+    pub fn generate_with_seed(&mut self, seed: u64) {
+        let mut rng = MazeRng::seed_from_u64(seed);
+        self.generate_with_rng(&mut rng);
+        self.seed = Some(seed);
+    }
 
-        while let Some((cx, cy)) = frontier.pop_front() {
-            let neighbors = self.get_unvisited_neighbors(cx, cy);
-            if !neighbors.is_empty() {
-                // Re-add the current cell if it has unvisited neighbors.
-                frontier.push_back((cx, cy));
-                // Choose a random unvisited neighbor.
-                let &(nx, ny) = neighbors.choose(&mut rng).unwrap();
-                // Remove the wall between the current cell and the neighbor.
-                self.remove_wall(cx, cy, nx, ny);
-                // Mark the neighbor as visited.
-                self.grid[nx][ny].visited = true;
-                // Add the neighbor to the frontier.
-                frontier.push_back((nx, ny));
-            }
-        }
+    // Generate the maze with the maze's algorithm, drawing all randomness from the given RNG.
+    pub fn generate_with_rng<R: RngCore>(&mut self, rng: &mut R) {
+        self.generate_passages(rng);
+        self.place_features(rng);
+        self.place_doors(0, self.doors.min(MAX_KEYS), rng);
     }
 
-    // Get the list of unvisited neighbors of a cell.
-    fn get_unvisited_neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
-        let mut neighbors = vec![];
-        if x > 0 && !self.grid[x - 1][y].visited {
-            neighbors.push((x - 1, y));  // Add the west neighbor.
+    // Carve and braid the maze, place its starts and lay its terrain, leaving out doors and features.
+    pub(crate) fn generate_passages<R: RngCore>(&mut self, rng: &mut R) {
+        // Start from a blank grid so regenerating never builds on a previous layout.
+        self.reset();
+        self.algorithm.generator().carve(self, rng);
+        if self.braid > 0.0 {
+            self.braid_with_rng(self.braid, rng);
         }
-        if x < self.width - 1 && !self.grid[x + 1][y].visited {
-            neighbors.push((x + 1, y));  // Add the east neighbor.
+        self.place_starts();
+        self.connect_to_treasure();
+        if self.rough > 0.0 {
+            self.place_terrain(self.rough, rng);
         }
-        if y > 0 && !self.grid[x][y - 1].visited {
-            neighbors.push((x, y - 1));  // Add the north neighbor.
+    }
+
+    // Cover the given fraction of cells in rough terrain, picking mud, water or rubble at random.
+    // Terrain only slows players down, so every cell stays as reachable as it was.
+    pub fn place_terrain<R: Rng + ?Sized>(&mut self, fraction: f64, rng: &mut R) {
+        let mut cells: Vec<(usize, usize)> = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .collect();
+        let count = (cells.len() as f64 * fraction.clamp(0.0, 1.0)).round() as usize;
+        let (rough, _) = cells.partial_shuffle(rng, count);
+        for &(x, y) in rough.iter() {
+            self.grid[x][y].terrain = *[Terrain::Mud, Terrain::Water, Terrain::Rubble].choose(rng).unwrap();
         }
-        if y < self.height - 1 && !self.grid[x][y + 1].visited {
-            neighbors.push((x, y + 1));  // Add the south neighbor.
+    }
+
+    // Place the start cells according to the start layout.
+    fn place_starts(&mut self) {
+        self.starts = match self.start_layout {
+            StartLayout::Shared => vec![(0, 0)],
+            StartLayout::PerPlayer(players) => {
+                // Walk the boundary clockwise from (0, 0) and hand out evenly spaced cells.
+                let boundary = self.boundary_cells();
+                (0..players).map(|i| boundary[i * boundary.len() / players.max(1)]).collect()
+            }
+        };
+    }
+
+    // Get the cells on the boundary of the maze, clockwise from (0, 0).
+    fn boundary_cells(&self) -> Vec<(usize, usize)> {
+        let (w, h) = (self.width, self.height);
+        if w == 1 || h == 1 {
+            return (0..w).flat_map(|x| (0..h).map(move |y| (x, y))).collect();
+        }
+        let mut cells = vec![];
+        cells.extend((0..w).map(|x| (x, 0)));                   // North edge, west to east.
+        cells.extend((1..h).map(|y| (w - 1, y)));               // East edge, north to south.
+        cells.extend((0..w - 1).rev().map(|x| (x, h - 1)));     // South edge, east to west.
+        cells.extend((1..h - 1).rev().map(|y| (0, y)));         // West edge, south to north.
+        cells
+    }
+
+    // Guarantee every cell, and so every start, is connected to the treasure. Each cell the
+    // treasure cannot reach gets joined to the reachable region by removing a single wall.
+    // Generated mazes are already connected, so this only changes a maze that was edited.
+    fn connect_to_treasure(&mut self) {
+        loop {
+            let reachable = self.reachable_from(self.treasure);
+            let mut bridge = None;
+            'search: for x in 0..self.width {
+                for y in 0..self.height {
+                    if !reachable[x][y] {
+                        continue;
+                    }
+                    for (nx, ny) in self.neighbors(x, y) {
+                        if !reachable[nx][ny] {
+                            bridge = Some((x, y, nx, ny));
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            match bridge {
+                Some((x, y, nx, ny)) => self.remove_wall(x, y, nx, ny),
+                None => return,
+            }
         }
-        neighbors
     }
 
-    // Remove the wall between two adjacent cells.
-    fn remove_wall(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
-        if x1 == x2 {
-            if y1 > y2 {
-                self.grid[x1][y1].walls[0] = false;  // Remove north wall of (x1, y1).
-                self.grid[x2][y2].walls[2] = false;  // Remove south wall of (x2, y2).
+    // Place the one-way passages, teleporter pairs and traps, each at a random spot. A feature that
+    // would leave a player stranded, unable to reach the treasure from somewhere they can get to,
+    // is taken back and tried elsewhere, so some may not fit in a small maze.
+    pub(crate) fn place_features<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        // Leave the RNG untouched when there is nothing to place, so mazes without features are
+        // the same as before features existed.
+        if self.one_ways + self.teleporters + self.traps == 0 {
+            return;
+        }
+        // Features stay off the starts and the treasure, and off each other.
+        let mut free: Vec<(usize, usize)> = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .filter(|cell| !self.starts.contains(cell) && *cell != self.treasure)
+            .collect();
+        free.shuffle(rng);
+
+        let mut passages: Vec<(usize, usize, usize)> = (0..self.width)
+            .flat_map(|x| (0..self.height).flat_map(move |y| (0..MAX_SIDES).map(move |side| (x, y, side))))
+            .filter(|&(x, y, side)| !self.grid[x][y].walls[side])
+            .collect();
+        passages.shuffle(rng);
+        let mut placed = 0;
+        for (x, y, side) in passages {
+            if placed == self.one_ways {
+                break;
+            }
+            let (nx, ny) = self.topology.neighbor(x, y, side, self.width, self.height).unwrap();
+            if self.grid[nx][ny].one_way[self.topology.opposite(side)] {
+                continue;
+            }
+            self.grid[x][y].one_way[side] = true;
+            if self.keeps_treasure_reachable() {
+                placed += 1;
             } else {
-                self.grid[x1][y1].walls[2] = false;  // Remove south wall of (x1, y1).
-                self.grid[x2][y2].walls[0] = false;  // Remove north wall of (x2, y2).
+                self.grid[x][y].one_way[side] = false;
             }
-        } else if y1 == y2 {
-            if x1 > x2 {
-                self.grid[x1][y1].walls[3] = false;  // Remove west wall of (x1, y1).
-                self.grid[x2][y2].walls[1] = false;  // Remove east wall of (x2, y2).
+        }
+
+        let mut placed = 0;
+        let mut pairs = free.clone();
+        while placed < self.teleporters && pairs.len() >= 2 {
+            let (a, b) = (pairs.pop().unwrap(), pairs.pop().unwrap());
+            self.grid[a.0][a.1].feature = Some(Feature::Teleporter { x: b.0, y: b.1 });
+            self.grid[b.0][b.1].feature = Some(Feature::Teleporter { x: a.0, y: a.1 });
+            if self.keeps_treasure_reachable() {
+                placed += 1;
             } else {
-                self.grid[x1][y1].walls[1] = false;  // Remove east wall of (x1, y1).
-                self.grid[x2][y2].walls[3] = false;  // Remove west wall of (x2, y2).
+                self.grid[a.0][a.1].feature = None;
+                self.grid[b.0][b.1].feature = None;
             }
         }
+
+        let mut placed = 0;
+        for (x, y) in free {
+            if placed == self.traps {
+                break;
+            }
+            if self.grid[x][y].feature.is_some() {
+                continue;
+            }
+            self.grid[x][y].feature = Some(if rng.gen_bool(0.5) { Feature::Pit } else { Feature::Trap { turns: rng.gen_range(1..=3) } });
+            if self.keeps_treasure_reachable() {
+                placed += 1;
+            } else {
+                self.grid[x][y].feature = None;
+            }
+        }
+    }
+
+    // Check that the treasure can be reached, ignoring doors, from every cell a player can get to
+    // from a start.
+    fn keeps_treasure_reachable(&self) -> bool {
+        // Search backwards from the treasure over every move in the maze.
+        let mut sources = vec![vec![vec![]; self.height]; self.width];
+        for x in 0..self.width {
+            for y in 0..self.height {
+                for (nx, ny) in self.moves(x, y, KeySet::MAX) {
+                    sources[nx][ny].push((x, y));
+                }
+            }
+        }
+        let (tx, ty) = self.treasure;
+        let mut leads_to_treasure = vec![vec![false; self.height]; self.width];
+        let mut stack = vec![(tx, ty)];
+        leads_to_treasure[tx][ty] = true;
+        while let Some((x, y)) = stack.pop() {
+            for &(sx, sy) in &sources[x][y] {
+                if !leads_to_treasure[sx][sy] {
+                    leads_to_treasure[sx][sy] = true;
+                    stack.push((sx, sy));
+                }
+            }
+        }
+        self.starts.iter().all(|&start| {
+            let (reachable, _) = self.reachable_with_keys(start, KeySet::MAX);
+            reachable.iter().flatten().zip(leads_to_treasure.iter().flatten()).all(|(&r, &l)| !r || l)
+        })
+    }
+
+    // Lock up to count passages on the way to the treasure behind doors, one door at a time,
+    // numbering their keys from first_key. Each key goes where every start can reach it with the
+    // keys placed before it, so collecting the keys in order always opens the way to the
+    // treasure. Returns the number of doors placed.
+    pub(crate) fn place_doors<R: Rng + ?Sized>(&mut self, first_key: u8, count: usize, rng: &mut R) -> usize {
+        // Without a start there is no way to the treasure to lock.
+        let Some(&first) = self.starts.first() else { return 0 };
+        for placed_doors in 0..count {
+            let key = first_key + placed_doors as u8;
+            let Some(path) = self.solution_path(first) else { return placed_doors };
+            let mut steps: Vec<usize> = (1..path.len()).collect();
+            steps.shuffle(rng);
+            let mut placed = false;
+            for i in steps {
+                let ((x1, y1), (x2, y2)) = (path[i - 1], path[i]);
+                // A step through a teleporter has no passage between its ends to lock.
+                if self.side_towards(x1, y1, x2, y2).is_none() || self.door_between(x1, y1, x2, y2).is_some() {
+                    continue;
+                }
+                self.set_door(x1, y1, x2, y2, Some(key));
+                // Cells every start reaches without the new key. A door that cuts one start off
+                // from another leaves none, and is moved elsewhere.
+                let mut common = vec![vec![true; self.height]; self.width];
+                for &start in &self.starts {
+                    let (reachable, _) = self.reachable_with_keys(start, 0);
+                    for (column, reached) in common.iter_mut().zip(reachable) {
+                        for (c, r) in column.iter_mut().zip(reached) {
+                            *c &= r;
+                        }
+                    }
+                }
+                let free: Vec<(usize, usize)> = (0..self.width)
+                    .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+                    .filter(|&(x, y)| common[x][y] && self.grid[x][y].key.is_none() && self.grid[x][y].feature.is_none())
+                    .collect();
+                if let Some(&(kx, ky)) = free.choose(rng) {
+                    self.grid[kx][ky].key = Some(key);
+                    placed = true;
+                    break;
+                }
+                self.set_door(x1, y1, x2, y2, None);
+            }
+            if !placed {
+                return placed_doors;
+            }
+        }
+        count
+    }
+
+    // Get the key lying in a cell as a key set.
+    pub fn keys_at(&self, x: usize, y: usize) -> KeySet {
+        self.grid[x][y].key.map_or(0, key_set)
+    }
+
+    // Get the key needed to pass between two adjacent cells, if there is a locked door.
+    pub fn door_between(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Option<u8> {
+        self.side_towards(x1, y1, x2, y2).and_then(|side| self.grid[x1][y1].doors[side])
+    }
+
+    // Put a door locked with a key on the passage between two adjacent cells, or remove it.
+    pub(crate) fn set_door(&mut self, x1: usize, y1: usize, x2: usize, y2: usize, key: Option<u8>) {
+        if let Some(side) = self.side_towards(x1, y1, x2, y2) {
+            self.grid[x1][y1].doors[side] = key;
+            self.grid[x2][y2].doors[self.topology.opposite(side)] = key;
+        }
+    }
+
+    // Check whether a player holding the given keys can step from one cell into an adjacent cell.
+    pub fn can_pass(&self, x1: usize, y1: usize, x2: usize, y2: usize, keys: KeySet) -> bool {
+        let Some(side) = self.side_towards(x1, y1, x2, y2) else { return false };
+        !self.grid[x1][y1].walls[side]
+            && !self.grid[x2][y2].one_way[self.topology.opposite(side)]
+            && self.grid[x1][y1].doors[side].is_none_or(|key| keys & key_set(key) != 0)
+    }
+
+    // Get where a player stepping into a cell ends up: the other end of a teleporter, nowhere in
+    // this maze for a pit, which drops them back at their start, or else the cell itself.
+    pub fn landing(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        match self.grid[x][y].feature {
+            Some(Feature::Teleporter { x: tx, y: ty }) if tx < self.width && ty < self.height => Some((tx, ty)),
+            Some(Feature::Teleporter { .. }) | Some(Feature::Pit) => None,
+            Some(Feature::Trap { .. }) | None => Some((x, y)),
+        }
+    }
+
+    // Get the extra turns a player stepping into a cell is held for.
+    pub fn delay(&self, x: usize, y: usize) -> usize {
+        match self.grid[x][y].feature {
+            Some(Feature::Trap { turns }) => turns as usize,
+            _ => 0,
+        }
+    }
+
+    // Get the turns it takes to step into a cell: its terrain's cost plus any trap's hold.
+    pub fn step_cost(&self, x: usize, y: usize) -> usize {
+        self.grid[x][y].terrain.cost() + self.delay(x, y)
+    }
+
+    // Get the turns a path takes, counting each step at the cost of the cell stepped into. A step
+    // between cells that are not adjacent goes through the teleporter paired with where it lands.
+    pub fn path_cost(&self, path: &[(usize, usize)]) -> usize {
+        path.windows(2)
+            .map(|step| {
+                let ((x1, y1), (x2, y2)) = (step[0], step[1]);
+                match (self.side_towards(x1, y1, x2, y2), self.grid[x2][y2].feature) {
+                    (None, Some(Feature::Teleporter { x, y })) => self.step_cost(x, y),
+                    _ => self.step_cost(x2, y2),
+                }
+            })
+            .sum()
+    }
+
+    // Get the cells a player holding the given keys can end up in with a single step, following
+    // teleporters and never stepping into a pit, since that only leads back to the start.
+    pub fn moves(&self, x: usize, y: usize, keys: KeySet) -> Vec<(usize, usize)> {
+        self.passable_neighbors(x, y, keys)
+            .into_iter()
+            .filter_map(|(nx, ny)| self.landing(nx, ny))
+            .collect()
+    }
+
+    // Get the neighbors of a cell a player holding the given keys can step to.
+    pub fn passable_neighbors(&self, x: usize, y: usize, keys: KeySet) -> Vec<(usize, usize)> {
+        self.neighbors(x, y)
+            .into_iter()
+            .filter(|&(nx, ny)| self.can_pass(x, y, nx, ny, keys))
+            .collect()
+    }
+
+    // Get which cells a player starting from a cell with the given keys can reach, picking up
+    // every key on the way, together with all the keys they end up holding.
+    pub fn reachable_with_keys(&self, (x, y): (usize, usize), mut keys: KeySet) -> (Vec<Vec<bool>>, KeySet) {
+        keys |= self.keys_at(x, y);
+        loop {
+            let mut reachable = vec![vec![false; self.height]; self.width];
+            let mut stack = vec![(x, y)];
+            reachable[x][y] = true;
+            let mut held = keys;
+            while let Some((cx, cy)) = stack.pop() {
+                for (nx, ny) in self.moves(cx, cy, held) {
+                    if !reachable[nx][ny] {
+                        reachable[nx][ny] = true;
+                        held |= self.keys_at(nx, ny);
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            // A key picked up late may open a door passed by earlier, so search again until no
+            // new keys turn up.
+            if held == keys {
+                return (reachable, keys);
+            }
+            keys = held;
+        }
+    }
+
+    // Get which cells can be reached from a cell without passing through a wall.
+    pub fn reachable_from(&self, (x, y): (usize, usize)) -> Vec<Vec<bool>> {
+        let mut reachable = vec![vec![false; self.height]; self.width];
+        let mut stack = vec![(x, y)];
+        reachable[x][y] = true;
+        while let Some((cx, cy)) = stack.pop() {
+            for (nx, ny) in self.open_neighbors(cx, cy) {
+                if !reachable[nx][ny] {
+                    reachable[nx][ny] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        reachable
+    }
+
+    // Braid the maze by knocking out walls at the given fraction of its dead ends, creating loops
+    // so there is more than one route between cells.
+    pub fn braid_with_rng<R: Rng + ?Sized>(&mut self, fraction: f64, rng: &mut R) {
+        let mut dead_ends = self.dead_ends();
+        let target = (dead_ends.len() as f64 * fraction.clamp(0.0, 1.0)).round() as usize;
+        dead_ends.shuffle(rng);
+
+        let mut removed = 0;
+        for (x, y) in dead_ends {
+            if removed >= target {
+                break;
+            }
+            // An earlier removal may already have opened this cell up.
+            if !self.is_dead_end(x, y) {
+                continue;
+            }
+
+            let closed: Vec<(usize, usize)> = self.neighbors(x, y)
+                .into_iter()
+                .filter(|&(nx, ny)| self.wall_between(x, y, nx, ny))
+                .collect();
+            // Prefer joining two dead ends, which removes both with a single wall.
+            let paired: Vec<(usize, usize)> = closed.iter()
+                .copied()
+                .filter(|&(nx, ny)| self.is_dead_end(nx, ny))
+                .collect();
+            let candidates = if paired.is_empty() { &closed } else { &paired };
+            if let Some(&(nx, ny)) = candidates.choose(rng) {
+                removed += 1 + self.is_dead_end(nx, ny) as usize;
+                self.remove_wall(x, y, nx, ny);
+            }
+        }
+    }
+
+    // Get every cell with exactly one opening.
+    pub fn dead_ends(&self) -> Vec<(usize, usize)> {
+        let mut dead_ends = vec![];
+        for x in 0..self.width {
+            for y in 0..self.height {
+                if self.is_dead_end(x, y) {
+                    dead_ends.push((x, y));
+                }
+            }
+        }
+        dead_ends
+    }
+
+    // Check whether a cell has exactly one opening.
+    pub fn is_dead_end(&self, x: usize, y: usize) -> bool {
+        self.grid[x][y].walls.iter().filter(|&&wall| !wall).count() == 1
+    }
+
+    // Check whether the wall between two adjacent cells is present.
+    pub fn wall_between(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> bool {
+        match self.side_towards(x1, y1, x2, y2) {
+            Some(side) => self.grid[x1][y1].walls[side],
+            None => true,
+        }
+    }
+
+    // Get the side of a cell that faces an adjacent cell.
+    pub fn side_towards(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Option<usize> {
+        (0..self.topology.sides())
+            .find(|&side| self.topology.neighbor(x1, y1, side, self.width, self.height) == Some((x2, y2)))
+    }
+
+    // Restore every cell to its initial state with all walls intact.
+    fn reset(&mut self) {
+        for (x, row) in self.grid.iter_mut().enumerate() {
+            for (y, cell) in row.iter_mut().enumerate() {
+                *cell = Cell::new(x, y);
+            }
+        }
+        self.seed = None;
+    }
+
+    // Get the neighbors of a cell that can be reached without passing through a wall.
+    pub fn open_neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        self.neighbors(x, y)
+            .into_iter()
+            .filter(|&(nx, ny)| !self.wall_between(x, y, nx, ny))
+            .collect()
+    }
+
+    // Find a cheapest path from a start cell to the treasure, counting terrain and traps. This is
+    // the path the dungeon commits to with commit_solution_path and reveals at the end of the
+    // game, so its cost proves the treasure can be reached within that many turns.
+    pub fn solution_path(&self, start: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        solver::dijkstra(self, start, self.treasure)
+    }
+
+    // Check the structure of the maze, returning every violation found. Players run this on a
+    // maze received from an untrusted dungeon, so it must not panic on malformed input.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = vec![];
+        if self.width == 0 || self.height == 0
+            || self.grid.len() != self.width
+            || self.grid.iter().any(|row| row.len() != self.height)
+        {
+            // Nothing else can be checked safely against a misshapen grid.
+            violations.push(Violation::GridSize { width: self.width, height: self.height });
+            return violations;
+        }
+
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let cell = &self.grid[x][y];
+                if (cell.x, cell.y) != (x, y) {
+                    violations.push(Violation::CellPosition { x, y });
+                }
+                // Key ids index a KeySet, so any id it cannot hold is reported, once per cell.
+                if let Some(key) = cell.key.into_iter().chain(cell.doors.iter().flatten().copied()).find(|&key| key as usize >= MAX_KEYS) {
+                    violations.push(Violation::InvalidKey { x, y, key });
+                }
+                // Check each shared wall from the cell that comes first in grid order, so it is only
+                // reported once, and every wall on the boundary.
+                for wall in 0..self.topology.sides() {
+                    if cell.doors[wall].is_some() && cell.walls[wall] {
+                        violations.push(Violation::MisplacedDoor { x, y, wall });
+                    }
+                    if cell.one_way[wall] && cell.walls[wall] {
+                        violations.push(Violation::MisplacedOneWay { x, y, wall });
+                    }
+                    match self.topology.neighbor(x, y, wall, self.width, self.height) {
+                        Some((nx, ny)) => {
+                            let back = self.topology.opposite(wall);
+                            if (nx, ny) > (x, y) && cell.walls[wall] != self.grid[nx][ny].walls[back] {
+                                violations.push(Violation::InconsistentWall { x, y, wall });
+                            }
+                            if (nx, ny) > (x, y) && cell.doors[wall] != self.grid[nx][ny].doors[back] {
+                                violations.push(Violation::InconsistentDoor { x, y, wall });
+                            }
+                            if (nx, ny) > (x, y) && cell.one_way[wall] && self.grid[nx][ny].one_way[back] {
+                                violations.push(Violation::MisplacedOneWay { x, y, wall });
+                            }
+                        }
+                        None if !cell.walls[wall] => {
+                            violations.push(Violation::MissingBoundaryWall { x, y, wall });
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        // Teleporters come in pairs, each leading to the other.
+        for cell in self.grid.iter().flatten() {
+            if let Some(Feature::Teleporter { x, y }) = cell.feature {
+                let paired = Some(Feature::Teleporter { x: cell.x, y: cell.y });
+                if x >= self.width || y >= self.height || (x, y) == (cell.x, cell.y) || self.grid[x][y].feature != paired {
+                    violations.push(Violation::BrokenTeleporter { x: cell.x, y: cell.y });
+                }
+            }
+        }
+
+        // Every door needs its key somewhere in the maze.
+        let cells = || self.grid.iter().flatten();
+        let keys: KeySet = cells().filter_map(|cell| cell.key).fold(0, |keys, key| keys | key_set(key));
+        let mut missing: Vec<u8> = cells()
+            .flat_map(|cell| cell.doors.iter().flatten().copied())
+            .filter(|&key| (key as usize) < MAX_KEYS && keys & key_set(key) == 0)
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        violations.extend(missing.into_iter().map(|key| Violation::MissingKey { key }));
+
+        if self.starts.is_empty() {
+            violations.push(Violation::NoStarts);
+        }
+        let in_bounds = |&(x, y): &(usize, usize)| x < self.width && y < self.height;
+        for &(x, y) in self.starts.iter().chain(std::iter::once(&self.treasure)) {
+            if !in_bounds(&(x, y)) {
+                violations.push(Violation::OutOfBounds { x, y });
+            }
+        }
+
+        // Every cell, starts included, must be connected to the treasure.
+        if in_bounds(&self.treasure) {
+            let reachable = self.reachable_from(self.treasure);
+            for (x, column) in reachable.iter().enumerate() {
+                for (y, &reached) in column.iter().enumerate() {
+                    if !reached {
+                        violations.push(Violation::Unreachable { x, y });
+                    }
+                }
+            }
+            // And every connected start must be able to collect its way through the doors, and
+            // around one-way passages and traps, to it.
+            let (tx, ty) = self.treasure;
+            for &(x, y) in self.starts.iter().filter(|&&(x, y)| in_bounds(&(x, y)) && reachable[x][y]) {
+                if !self.reachable_with_keys((x, y), 0).0[tx][ty] {
+                    violations.push(Violation::Locked { x, y });
+                }
+            }
+        }
+        violations
+    }
+
+    // Get the list of all in-bounds neighbors of a cell, regardless of walls.
+    pub(crate) fn neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        (0..self.topology.sides())
+            .filter_map(|side| self.topology.neighbor(x, y, side, self.width, self.height))
+            .collect()
+    }
+
+    // Get the list of unvisited neighbors of a cell.
+    pub(crate) fn get_unvisited_neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        self.neighbors(x, y)
+            .into_iter()
+            .filter(|&(nx, ny)| !self.grid[nx][ny].visited)
+            .collect()
+    }
+
+    // Remove the wall between two adjacent cells. Does nothing if the cells are not adjacent.
+    pub(crate) fn remove_wall(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        if let Some(side) = self.side_towards(x1, y1, x2, y2) {
+            self.grid[x1][y1].walls[side] = false;                          // Remove the wall of (x1, y1) facing (x2, y2).
+            self.grid[x2][y2].walls[self.topology.opposite(side)] = false;  // Remove the matching wall of (x2, y2).
+        }
     }
 
     // Generate a masked subset of the maze based on the player's exploration.
-    pub fn get_masked_maze(&self, mask: &Vec<Vec<bool>>) -> Maze {
+    pub fn get_masked_maze(&self, mask: &[Vec<bool>]) -> Maze {
         let mut masked_grid = vec![];
-        for x in 0..self.width {
+        for (x, (column, visible)) in self.grid.iter().zip(mask).enumerate() {
             let mut row = vec![];
-            for y in 0..self.height {
-                if mask[x][y] {
+            for (y, (&cell, &visible)) in column.iter().zip(visible).enumerate() {
+                if visible {
                     // Add the cell if it's visible in the mask.
-                    row.push(self.grid[x][y]);
+                    row.push(cell);
                 } else {
                     // Add a new cell with all walls intact if it's not visible.
                     row.push(Cell::new(x, y));
@@ -129,48 +827,86 @@ impl Maze {
         Maze {
             width: self.width,
             height: self.height,
+            topology: self.topology,
             grid: masked_grid,
+            seed: None, // The seed would reveal the whole maze, so it is never part of a view.
+            algorithm: self.algorithm,
+            braid: self.braid,
+            rough: self.rough,
+            doors: self.doors,
+            one_ways: self.one_ways,
+            teleporters: self.teleporters,
+            traps: self.traps,
+            start_layout: self.start_layout,
+            starts: self.starts.clone(),
+            treasure: self.treasure,
         }
     }
 
     // Commit the maze generation solution path.
-    pub fn commit_solution_path(&self, solution_path: &[(usize, usize)]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        for &(x, y) in solution_path {
-            hasher.update(format!("{},{}", x, y).as_bytes());
-        }
-        hasher.finalize().to_vec()
+    pub fn commit_solution_path<R: RngCore>(&self, solution_path: &[(usize, usize)], rng: &mut R) -> (Commitment, Opening) {
+        commitment::commit(Domain::SolutionPath, solution_path, rng)
     }
 
-    // Display the maze in ASCII format.
-    pub fn display(&self) {
-        for row in &self.grid {
-            // Top line of cells.
-            for cell in row {
-                if cell.walls[0] {
-                    print!("+---");  // Print the north wall.
-                } else {
-                    print!("+   ");
-                }
-            }
-            println!("+");
+    // Commit to the full layout of the maze: its dimensions, topology, start cells, treasure, walls,
+    // doors, keys, one-way passages, features and terrain. The opening stays with the dungeon
+    // until the layout is revealed.
+    pub fn commit_layout<R: RngCore>(&self, rng: &mut R) -> (Commitment, Opening) {
+        commitment::commit(Domain::Layout, self, rng)
+    }
 
-            // Middle line of cells.
-            for cell in row {
-                if cell.walls[3] {
-                    print!("|   ");  // Print the west wall.
-                } else {
-                    print!("    ");
-                }
-            }
-            println!("|");
-        }
+    // Get the commitment to the layout under a given opening, to check a revealed layout against.
+    pub fn layout_commitment(&self, opening: &Opening) -> Commitment {
+        commitment::commit_with(Domain::Layout, self, opening)
+    }
 
-        // Bottom line of the maze.
-        for _ in 0..self.width {
-            print!("+---");
-        }
-        println!("+");
+    // Encode everything about a cell a commitment covers: its walls, doors, key, one-way sides,
+    // feature and terrain.
+    pub fn cell_layout(&self, cell: &Cell) -> Vec<u8> {
+        let sides = self.topology.sides();
+        let mut bytes = vec![];
+        cell.walls[..sides].encode(&mut bytes);
+        cell.doors[..sides].encode(&mut bytes);
+        cell.key.encode(&mut bytes);
+        cell.one_way[..sides].encode(&mut bytes);
+        cell.feature.encode(&mut bytes);
+        cell.terrain.encode(&mut bytes);
+        bytes
+    }
+
+    // Pack the walls of a square maze into bits (see dungeon::compact).
+    pub fn to_compact(&self) -> Option<CompactMaze> {
+        CompactMaze::from_maze(self)
+    }
+
+    // Encode the maze in the versioned binary format (see dungeon::format).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format::encode(self)
+    }
+
+    // Decode a maze from the versioned binary format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Maze, FormatError> {
+        format::decode(bytes)
+    }
+
+    // Save the maze to a file in the binary format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    // Load a maze from a file in the binary format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Maze, FormatError> {
+        Maze::from_bytes(&fs::read(path)?)
+    }
+
+    // Render the maze in ASCII format, marking the starts and the treasure.
+    pub fn to_ascii(&self) -> String {
+        render::render(self, &Overlay::for_maze(self))
+    }
+
+    // Display the maze in ASCII format.
+    pub fn display(&self) {
+        print!("{}", self.to_ascii());
     }
 }
 
@@ -186,6 +922,40 @@ mod tests {
         assert!(maze.grid.iter().all(|row| row.iter().all(|cell| cell.visited)));
     }
 
+    #[test]
+    fn test_seeded_generation_is_reproducible() {
+        let first = Maze::from_seed(8, 6, 42);
+        let mut second = Maze::new(8, 6);
+        second.generate_with_seed(42);
+        assert_eq!(first.grid, second.grid);
+        assert_eq!(second.seed, Some(42));
+
+        // Regenerating an already generated maze must not depend on its previous layout.
+        second.generate_with_seed(7);
+        second.generate_with_seed(42);
+        assert_eq!(first.grid, second.grid);
+
+        let other = Maze::from_seed(8, 6, 43);
+        assert_ne!(first.grid, other.grid);
+    }
+
+    #[test]
+    fn test_braiding_removes_dead_ends() {
+        let perfect = Maze::from_seed(12, 12, 5);
+        let before = perfect.dead_ends().len();
+        assert!(before > 0);
+
+        let mut maze = Maze::new(12, 12);
+        maze.braid = 0.5;
+        maze.generate_with_seed(5);
+        let after = maze.dead_ends().len();
+        assert!(after < before && after > 0);
+
+        maze.braid = 1.0;
+        maze.generate_with_seed(5);
+        assert!(maze.dead_ends().is_empty());
+    }
+
     #[test]
     fn test_masked_maze() {
         let mut maze = Maze::new(5, 5);
@@ -202,11 +972,221 @@ mod tests {
         assert!(!masked_maze.grid[0][1].visited); // Check that masked cells are not visited.
     }
 
+    #[test]
+    fn test_solution_path_reaches_treasure() {
+        let maze = Maze::from_seed(7, 7, 9);
+        let path = maze.solution_path((0, 0)).unwrap();
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&maze.treasure));
+        let (commitment, opening) = maze.commit_solution_path(&path, &mut MazeRng::seed_from_u64(1));
+        assert!(commitment::verify(Domain::SolutionPath, &commitment, &path, &opening));
+    }
+
+    #[test]
+    fn test_starts_connected_to_treasure() {
+        let mut maze = Maze::new(9, 7);
+        maze.start_layout = StartLayout::PerPlayer(4);
+        maze.generate_with_seed(2);
+        assert_eq!(maze.treasure, (4, 3));
+        assert_eq!(maze.starts, vec![(0, 0), (7, 0), (8, 6), (1, 6)]);
+        let reachable = maze.reachable_from(maze.treasure);
+        assert!(reachable.iter().flatten().all(|&r| r));
+        for &start in &maze.starts {
+            assert!(maze.solution_path(start).is_some());
+        }
+
+        // Walling the treasure in gets undone when the maze is reconnected.
+        let (tx, ty) = maze.treasure;
+        for (nx, ny) in maze.neighbors(tx, ty) {
+            maze.grid[tx][ty].walls = [true; MAX_SIDES];
+            let back = maze.side_towards(nx, ny, tx, ty).unwrap();
+            maze.grid[nx][ny].walls[back] = true;
+        }
+        assert!(maze.solution_path((0, 0)).is_none());
+        maze.connect_to_treasure();
+        assert!(maze.solution_path((0, 0)).is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut maze = Maze::new(6, 5);
+        maze.braid = 0.5;
+        maze.generate_with_seed(6);
+        assert!(maze.validate().is_empty());
+
+        // Open a passage on one side only.
+        let mut malformed = maze.clone();
+        malformed.grid[2][2].walls[1] = !malformed.grid[2][2].walls[1];
+        assert_eq!(malformed.validate(), vec![Violation::InconsistentWall { x: 2, y: 2, wall: 1 }]);
+
+        // Open the outer wall at the south east corner.
+        let mut malformed = maze.clone();
+        malformed.grid[5][4].walls[2] = false;
+        assert_eq!(malformed.validate(), vec![Violation::MissingBoundaryWall { x: 5, y: 4, wall: 2 }]);
+
+        // Seal off the start in the north west corner.
+        let mut malformed = maze.clone();
+        malformed.grid[0][0].walls = [true; MAX_SIDES];
+        malformed.grid[1][0].walls[3] = true;
+        malformed.grid[0][1].walls[0] = true;
+        assert_eq!(malformed.validate(), vec![Violation::Unreachable { x: 0, y: 0 }]);
+
+        let mut malformed = maze.clone();
+        malformed.grid.pop();
+        assert_eq!(malformed.validate(), vec![Violation::GridSize { width: 6, height: 5 }]);
+
+        let mut malformed = maze;
+        malformed.starts.push((6, 0));
+        assert_eq!(malformed.validate(), vec![Violation::OutOfBounds { x: 6, y: 0 }]);
+    }
+
+    #[test]
+    fn test_validate_rejects_key_ids_beyond_key_sets() {
+        let mut maze = Maze::new(4, 4);
+        maze.generate_with_seed(3);
+        // A key id too large to shift into a KeySet, as a deserialized maze can carry.
+        maze.grid[1][1].key = Some(40);
+        assert_eq!(maze.keys_at(1, 1), 0);
+        assert_eq!(maze.validate(), vec![Violation::InvalidKey { x: 1, y: 1, key: 40 }]);
+
+        // A door locked with such a key is reported on both sides, and never opens.
+        let (nx, ny) = maze.passable_neighbors(0, 0, 0)[0];
+        maze.set_door(0, 0, nx, ny, Some(40));
+        assert!(!maze.can_pass(0, 0, nx, ny, KeySet::MAX));
+        let violations = maze.validate();
+        assert!(violations.contains(&Violation::InvalidKey { x: 0, y: 0, key: 40 }));
+        assert!(violations.contains(&Violation::InvalidKey { x: nx, y: ny, key: 40 }));
+        assert!(!violations.iter().any(|violation| matches!(violation, Violation::MissingKey { .. })));
+    }
+
+    #[test]
+    fn test_doors_keep_treasure_reachable() {
+        let opening = Opening { nonce: [7; 32] };
+        for (seed, layout) in [(1, StartLayout::Shared), (2, StartLayout::PerPlayer(4))] {
+            let mut maze = Maze::new(10, 8);
+            maze.doors = 3;
+            maze.start_layout = layout;
+            maze.generate_with_seed(seed);
+            let doors: Vec<u8> = maze.grid.iter().flatten().flat_map(|cell| cell.doors.iter().flatten().copied()).collect();
+            assert_eq!(doors.len(), 3 * 2); // Each door is stored on both of its cells.
+            assert!(maze.validate().is_empty());
+
+            // Without its keys a start cannot get past the doors to the treasure.
+            let (tx, ty) = maze.treasure;
+            let mut keyless = maze.clone();
+            for cell in keyless.grid.iter_mut().flatten() {
+                cell.key = None;
+            }
+            assert!(!keyless.reachable_with_keys(maze.starts[0], 0).0[tx][ty]);
+            assert!(keyless.validate().contains(&Violation::MissingKey { key: 0 }));
+            assert_ne!(keyless.layout_commitment(&opening), maze.layout_commitment(&opening));
+        }
+
+        // A door must sit across an open passage.
+        let mut maze = Maze::from_seed(5, 5, 3);
+        let (x, y) = (0, 0);
+        let side = (0..4).find(|&side| maze.grid[x][y].walls[side]).unwrap();
+        maze.grid[x][y].doors[side] = Some(0);
+        maze.grid[4][4].key = Some(0);
+        assert!(maze.validate().contains(&Violation::MisplacedDoor { x, y, wall: side }));
+    }
+
+    #[test]
+    fn test_features_and_terrain_keep_treasure_reachable() {
+        let opening = Opening { nonce: [7; 32] };
+        let mut maze = Maze::new(12, 10);
+        maze.braid = 0.5;
+        maze.doors = 2;
+        maze.one_ways = 6;
+        maze.teleporters = 2;
+        maze.traps = 4;
+        maze.rough = 0.25;
+        maze.start_layout = StartLayout::PerPlayer(2);
+        maze.generate_with_seed(5);
+        assert!(maze.validate().is_empty());
+        for &start in &maze.starts {
+            assert!(maze.solution_path(start).is_some());
+        }
+        let cells: Vec<&Cell> = maze.grid.iter().flatten().collect();
+        assert_eq!(cells.iter().map(|cell| cell.one_way.iter().filter(|&&one_way| one_way).count()).sum::<usize>(), 6);
+        assert_eq!(cells.iter().filter(|cell| matches!(cell.feature, Some(Feature::Teleporter { .. }))).count(), 2 * 2);
+        assert_eq!(cells.iter().filter(|cell| matches!(cell.feature, Some(Feature::Trap { .. } | Feature::Pit))).count(), 4);
+        assert_eq!(cells.iter().filter(|cell| cell.terrain != Terrain::Corridor).count(), 30);
+
+        // The committed solution is the cheapest path, never costing more than the shortest one.
+        let start = maze.starts[0];
+        let cheapest = maze.solution_path(start).unwrap();
+        let shortest = solver::bfs(&maze, start, maze.treasure).unwrap();
+        assert!(maze.path_cost(&cheapest) <= maze.path_cost(&shortest));
+
+        // Features are only shown in explored cells, and are part of the commitment.
+        let (x, y) = cells.iter().find(|cell| cell.feature.is_some()).map(|cell| (cell.x, cell.y)).unwrap();
+        let mut mask = vec![vec![false; 10]; 12];
+        assert_eq!(maze.get_masked_maze(&mask).grid[x][y].feature, None);
+        mask[x][y] = true;
+        assert_eq!(maze.get_masked_maze(&mask).grid[x][y].feature, maze.grid[x][y].feature);
+        let mut without = maze.clone();
+        without.grid[x][y].feature = None;
+        assert_ne!(without.layout_commitment(&opening), maze.layout_commitment(&opening));
+
+        // A teleporter has to lead to one leading back.
+        let mut broken = maze.clone();
+        broken.grid[0][0].feature = Some(Feature::Teleporter { x: 20, y: 0 });
+        assert!(broken.validate().contains(&Violation::BrokenTeleporter { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn test_hex_and_triangle_mazes() {
+        let opening = Opening { nonce: [7; 32] };
+        for topology in [Topology::Hex, Topology::Triangle] {
+            let mut maze = Maze::new(7, 6);
+            maze.topology = topology;
+            maze.braid = 0.5;
+            maze.start_layout = StartLayout::PerPlayer(3);
+            maze.generate_with_seed(4);
+            assert!(maze.validate().is_empty(), "{:?}", topology);
+            for &start in &maze.starts {
+                assert!(maze.solution_path(start).is_some(), "{:?}", topology);
+            }
+
+            // Sides past the topology's own count are never opened.
+            let sides = topology.sides();
+            assert!(maze.grid.iter().flatten().all(|cell| cell.walls[sides..].iter().all(|&wall| wall)));
+
+            let mut square = maze.clone();
+            square.topology = Topology::Square;
+            assert_ne!(square.layout_commitment(&opening), maze.layout_commitment(&opening));
+        }
+    }
+
+    #[test]
+    fn test_layout_serialization_and_commitment() {
+        let opening = Opening { nonce: [7; 32] };
+        let mut maze = Maze::new(6, 6);
+        maze.start_layout = StartLayout::PerPlayer(2);
+        maze.generate_with_seed(8);
+        let json = serde_json::to_vec(&maze).unwrap();
+        let decoded: Maze = serde_json::from_slice(&json).unwrap();
+        assert_eq!(decoded.grid, maze.grid);
+        assert_eq!(decoded.starts, maze.starts);
+        assert_eq!(decoded.treasure, maze.treasure);
+        assert_eq!(decoded.layout_commitment(&opening), maze.layout_commitment(&opening));
+
+        let mut moved = decoded.clone();
+        moved.treasure = (0, 0);
+        assert_ne!(moved.layout_commitment(&opening), maze.layout_commitment(&opening));
+    }
+
     #[test]
     fn test_commit_solution_path() {
         let maze = Maze::new(5, 5);
         let solution_path = vec![(0, 0), (0, 1), (1, 1), (2, 1), (2, 2)];
-        let commitment = maze.commit_solution_path(&solution_path);
-        assert_eq!(commitment.len(), 32); // Check the length of the SHA-256 hash.
+        let mut rng = MazeRng::seed_from_u64(5);
+        let (commitment, opening) = maze.commit_solution_path(&solution_path, &mut rng);
+        assert!(commitment::verify(Domain::SolutionPath, &commitment, &solution_path, &opening));
+        assert!(!commitment::verify(Domain::SolutionPath, &commitment, &solution_path[1..], &opening));
+        // Committing again to the same path gives a different commitment, so the path cannot be
+        // guessed by committing to every candidate.
+        assert_ne!(maze.commit_solution_path(&solution_path, &mut rng).0, commitment);
     }
 }
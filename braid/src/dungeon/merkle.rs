@@ -0,0 +1,288 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use crate::dungeon::maze::Maze;
+use crate::dungeon::commitment::Encode;
+
+/*
+ * - Merkle Tree: A binary hash tree over a list of leaves. A node without a sibling is carried up
+ *   to the next level unchanged, and leaves and inner nodes are hashed with different prefixes so
+ *   one can never pass for the other.
+ * - Cell Tree: A Merkle tree with one leaf per cell of a maze, in [x][y] order, whose root the
+ *   dungeon commits to before the game. Each leaf covers the cell's position and everything
+ *   commit_layout covers about it, salted.
+ * - Salts: Every cell has its own salt, derived from a secret kept by the dungeon, so revealing a
+ *   cell reveals nothing about the cells around it, not even whether they are walled in.
+ * - Cell Proof: The salt and sibling hashes a player needs to check one revealed cell against the
+ *   committed root.
+ * - Proven View: A masked view together with a proof for every revealed cell, so a player can
+ *   later prove exactly which walls they were shown, and the dungeon cannot change the maze
+ *   mid-game without the proofs failing.
+ */
+
+// A SHA-256 digest.
+pub type Hash = [u8; 32];
+
+// Hash a leaf, prefixed so it can never be mistaken for an inner node.
+fn hash_leaf(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+// Hash two sibling nodes into their parent.
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// Binary hash tree, keeping every level so proofs can be read off it.
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,     // Levels from the leaf hashes up to the root.
+}
+
+impl MerkleTree {
+    // Build a tree over leaf data, hashing each leaf.
+    pub fn new<T: AsRef<[u8]>>(leaves: &[T]) -> Self {
+        let mut levels = vec![leaves.iter().map(|leaf| hash_leaf(leaf.as_ref())).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [lone] => *lone,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    // Number of leaves.
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    // Get the root, or the hash of nothing for a tree without leaves.
+    pub fn root(&self) -> Hash {
+        self.levels.last().unwrap().first().copied().unwrap_or_else(|| Sha256::digest([]).into())
+    }
+
+    // Get the sibling hashes on the way from a leaf up to the root, skipping levels where the
+    // leaf's ancestor has no sibling.
+    pub fn proof(&self, index: usize) -> Vec<Hash> {
+        let mut siblings = vec![];
+        let mut i = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(i ^ 1) {
+                siblings.push(*sibling);
+            }
+            i /= 2;
+        }
+        siblings
+    }
+}
+
+// Check that a leaf is the given one of leaf_count leaves under a root. The shape of the tree
+// follows from leaf_count, which tells which levels have a sibling to combine with.
+pub fn verify_leaf(root: &Hash, leaf: &[u8], index: usize, leaf_count: usize, siblings: &[Hash]) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let mut hash = hash_leaf(leaf);
+    let mut siblings = siblings.iter();
+    let (mut i, mut count) = (index, leaf_count);
+    while count > 1 {
+        if i ^ 1 < count {
+            let Some(sibling) = siblings.next() else {
+                return false;
+            };
+            hash = if i % 2 == 0 { hash_node(&hash, sibling) } else { hash_node(sibling, &hash) };
+        }
+        i /= 2;
+        count = count.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+// Derive the salt of a cell from the dungeon's secret.
+fn cell_salt(secret: &Hash, x: usize, y: usize) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(b"cell salt");
+    hasher.update(secret);
+    hasher.update(format!("{},{}", x, y).as_bytes());
+    hasher.finalize().into()
+}
+
+// Encode the leaf of a cell: the maze's shape, the cell's position and salt, and its layout.
+fn cell_leaf(maze: &Maze, x: usize, y: usize, salt: &Hash) -> Vec<u8> {
+    let mut leaf = vec![];
+    ((maze.width, maze.height), maze.topology, (x, y)).encode(&mut leaf);
+    leaf.extend(salt);
+    leaf.extend(maze.cell_layout(&maze.grid[x][y]));
+    leaf
+}
+
+// Merkle tree over the cells of a maze, kept by the dungeon together with the secret its salts
+// come from.
+#[derive(Clone, Debug)]
+pub struct CellTree {
+    secret: Hash,               // Secret every cell's salt is derived from.
+    tree: MerkleTree,           // Tree over the salted cells, in [x][y] order.
+}
+
+// Proof that a revealed cell is the one committed to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellProof {
+    pub x: usize,               // X coordinate of the cell.
+    pub y: usize,               // Y coordinate of the cell.
+    pub salt: Hash,             // Salt of the cell's leaf.
+    pub siblings: Vec<Hash>,    // Sibling hashes from the leaf up to the root.
+}
+
+// A masked view with a proof for every revealed cell.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProvenView {
+    pub view: Maze,             // The masked view, as from Maze::get_masked_maze.
+    pub proofs: Vec<CellProof>, // One proof per revealed cell.
+}
+
+impl CellTree {
+    // Build the tree over every cell of a maze, salted from a secret.
+    pub fn new(maze: &Maze, secret: Hash) -> Self {
+        let leaves: Vec<Vec<u8>> = (0..maze.width)
+            .flat_map(|x| (0..maze.height).map(move |y| (x, y)))
+            .map(|(x, y)| cell_leaf(maze, x, y, &cell_salt(&secret, x, y)))
+            .collect();
+        CellTree { secret, tree: MerkleTree::new(&leaves) }
+    }
+
+    // Get the root the dungeon commits to.
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    // Prove a cell of the maze the tree was built over.
+    pub fn prove(&self, maze: &Maze, x: usize, y: usize) -> CellProof {
+        CellProof {
+            x,
+            y,
+            salt: cell_salt(&self.secret, x, y),
+            siblings: self.tree.proof(x * maze.height + y),
+        }
+    }
+
+    // Prove every cell revealed by a mask indexed [x][y].
+    pub fn prove_mask(&self, maze: &Maze, mask: &[Vec<bool>]) -> Vec<CellProof> {
+        (0..maze.width)
+            .flat_map(|x| (0..maze.height).map(move |y| (x, y)))
+            .filter(|&(x, y)| mask[x][y])
+            .map(|(x, y)| self.prove(maze, x, y))
+            .collect()
+    }
+
+    // Generate the masked view of a maze from a mask indexed [x][y], with a proof for every
+    // revealed cell.
+    pub fn get_proven_view(&self, maze: &Maze, mask: &[Vec<bool>]) -> ProvenView {
+        ProvenView { view: maze.get_masked_maze(mask), proofs: self.prove_mask(maze, mask) }
+    }
+}
+
+impl CellProof {
+    // Check the cell at this proof's position in a view against a committed root. A view whose
+    // grid does not match its dimensions fails, whatever it holds.
+    pub fn verify(&self, view: &Maze, root: &Hash) -> bool {
+        let fits = view.grid.len() == view.width && view.grid.iter().all(|column| column.len() == view.height);
+        if !fits || self.x >= view.width || self.y >= view.height {
+            return false;
+        }
+        let leaf = cell_leaf(view, self.x, self.y, &self.salt);
+        verify_leaf(root, &leaf, self.x * view.height + self.y, view.width * view.height, &self.siblings)
+    }
+}
+
+impl ProvenView {
+    // Check every revealed cell against a committed root.
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.proofs.iter().all(|proof| proof.verify(&self.view, root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proofs_for_every_tree_shape() {
+        for count in 1..12 {
+            let leaves: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8]).collect();
+            let tree = MerkleTree::new(&leaves);
+            let root = tree.root();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(i);
+                assert!(verify_leaf(&root, leaf, i, count, &proof));
+                assert!(!verify_leaf(&root, &[0xff], i, count, &proof));
+                if count > 1 {
+                    assert!(!verify_leaf(&root, leaf, (i + 1) % count, count, &proof));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_proven_view_binds_walls() {
+        let maze = Maze::from_seed(7, 5, 4);
+        let tree = CellTree::new(&maze, [7; 32]);
+        let root = tree.root();
+        let mut mask = vec![vec![false; 5]; 7];
+        mask[0][0] = true;
+        mask[3][2] = true;
+        let proven = tree.get_proven_view(&maze, &mask);
+        assert_eq!(proven.proofs.len(), 2);
+        assert!(proven.verify(&root));
+
+        // A view showing a wall the maze does not have fails its proof.
+        let mut forged = proven.clone();
+        let walls = &mut forged.view.grid[3][2].walls;
+        walls[0] = !walls[0];
+        assert!(!forged.verify(&root));
+
+        // The root changes with any wall, and with the secret.
+        let mut changed = maze.clone();
+        changed.grid[6][4].walls[0] = !changed.grid[6][4].walls[0];
+        assert_ne!(CellTree::new(&changed, [7; 32]).root(), root);
+        assert_ne!(CellTree::new(&maze, [8; 32]).root(), root);
+    }
+
+    #[test]
+    fn test_salts_differ_per_cell() {
+        let maze = Maze::from_seed(4, 4, 1);
+        let tree = CellTree::new(&maze, [3; 32]);
+        // A proof moved to another cell does not check out, even with the other cell's layout.
+        let mut moved = tree.prove(&maze, 1, 1);
+        assert_ne!(moved.salt, tree.prove(&maze, 1, 2).salt);
+        moved.y = 2;
+        assert!(!moved.verify(&maze, &tree.root()));
+        assert!(tree.prove(&maze, 1, 2).verify(&maze, &tree.root()));
+
+        // Proofs outside the view, or against a grid that does not match its dimensions, fail
+        // rather than panic.
+        moved.y = 4;
+        assert!(!moved.verify(&maze, &tree.root()));
+        let mut short = maze.clone();
+        short.grid[1].pop();
+        assert!(!tree.prove(&maze, 1, 2).verify(&short, &tree.root()));
+        short.grid.pop();
+        short.width = 3;
+        assert!(!tree.prove(&maze, 1, 2).verify(&short, &tree.root()));
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::Maze;
+
+/*
+ * - Maze Metrics: Difficulty and fairness measurements of a generated maze.
+ * - Difficulty Target: Bounds a dungeon operator places on those measurements.
+ * - Generate Until Target: Regenerates a maze from successive seeds until it meets a target.
+ */
+
+// Difficulty and fairness measurements of a maze.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MazeMetrics {
+    pub solution_length: usize,         // Steps from the first start to the treasure.
+    pub dead_ends: usize,               // Number of cells with a single opening.
+    pub branching_factor: f64,          // Average number of onward choices along the solution path.
+    pub river_factor: f64,              // Average length of the corridors leading into dead ends.
+    pub mean_start_distance: f64,       // Average steps from each start to the treasure.
+    pub start_distance_variance: f64,   // Variance of those distances. Zero when every start is equally far.
+}
+
+impl MazeMetrics {
+    // Measure a maze. None if some start cannot reach the treasure.
+    pub fn of(maze: &Maze) -> Option<Self> {
+        let mut distances = vec![];
+        for &start in &maze.starts {
+            distances.push((maze.solution_path(start)?.len() - 1) as f64);
+        }
+        let solution = maze.solution_path(*maze.starts.first()?)?;
+
+        let count = distances.len() as f64;
+        let mean_start_distance = distances.iter().sum::<f64>() / count;
+        let start_distance_variance = distances.iter()
+            .map(|d| (d - mean_start_distance).powi(2))
+            .sum::<f64>() / count;
+
+        let dead_ends = maze.dead_ends();
+        Some(MazeMetrics {
+            solution_length: solution.len() - 1,
+            dead_ends: dead_ends.len(),
+            branching_factor: branching_factor(maze, &solution),
+            river_factor: river_factor(maze, &dead_ends),
+            mean_start_distance,
+            start_distance_variance,
+        })
+    }
+}
+
+// Average number of passages a player could take at each cell of the solution path, not
+// counting the passage they arrived through.
+fn branching_factor(maze: &Maze, solution: &[(usize, usize)]) -> f64 {
+    let steps = &solution[..solution.len() - 1];
+    if steps.is_empty() {
+        return 0.0;
+    }
+    let choices: usize = steps.iter()
+        .enumerate()
+        .map(|(i, &(x, y))| {
+            let openings = maze.open_neighbors(x, y).len();
+            if i == 0 { openings } else { openings - 1 }
+        })
+        .sum();
+    choices as f64 / steps.len() as f64
+}
+
+// Average length of the corridors that end in a dead end, walked back from the dead end until
+// reaching a junction. Mazes with few, long dead ends "flow" like a river and are harder to
+// rule out at a glance than mazes with many short stubs.
+fn river_factor(maze: &Maze, dead_ends: &[(usize, usize)]) -> f64 {
+    if dead_ends.is_empty() {
+        return 0.0;
+    }
+    let mut total = 0;
+    for &start in dead_ends {
+        let (mut previous, mut current) = (None, start);
+        loop {
+            let onward: Vec<(usize, usize)> = maze.open_neighbors(current.0, current.1)
+                .into_iter()
+                .filter(|&cell| Some(cell) != previous)
+                .collect();
+            // Stop at a junction, or at the far end of a corridor with no junction at all.
+            if onward.len() != 1 {
+                break;
+            }
+            total += 1;
+            previous = Some(current);
+            current = onward[0];
+            if maze.open_neighbors(current.0, current.1).len() > 2 {
+                break;
+            }
+        }
+    }
+    total as f64 / dead_ends.len() as f64
+}
+
+// Bounds a generated maze has to meet. Every bound is inclusive.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DifficultyTarget {
+    pub solution_length: (usize, usize),
+    pub dead_ends: (usize, usize),
+    pub branching_factor: (f64, f64),
+    pub river_factor: (f64, f64),
+    pub max_start_distance_variance: f64,
+}
+
+impl Default for DifficultyTarget {
+    // A target every solvable maze meets.
+    fn default() -> Self {
+        DifficultyTarget {
+            solution_length: (0, usize::MAX),
+            dead_ends: (0, usize::MAX),
+            branching_factor: (0.0, f64::INFINITY),
+            river_factor: (0.0, f64::INFINITY),
+            max_start_distance_variance: f64::INFINITY,
+        }
+    }
+}
+
+impl DifficultyTarget {
+    // Check whether measured metrics fall within the target.
+    pub fn accepts(&self, metrics: &MazeMetrics) -> bool {
+        let within = |value: f64, (min, max): (f64, f64)| value >= min && value <= max;
+        (self.solution_length.0..=self.solution_length.1).contains(&metrics.solution_length)
+            && (self.dead_ends.0..=self.dead_ends.1).contains(&metrics.dead_ends)
+            && within(metrics.branching_factor, self.branching_factor)
+            && within(metrics.river_factor, self.river_factor)
+            && metrics.start_distance_variance <= self.max_start_distance_variance
+    }
+}
+
+// Regenerate the maze from seed, seed + 1, ... until it meets the target, giving up after
+// max_attempts. The maze keeps its configured algorithm, braiding and start layout, and records
+// the seed that was accepted so a verifier can rebuild it. Returns the accepted maze's metrics.
+pub fn generate_until_target(maze: &mut Maze, target: &DifficultyTarget, seed: u64, max_attempts: usize) -> Option<MazeMetrics> {
+    for attempt in 0..max_attempts {
+        maze.generate_with_seed(seed.wrapping_add(attempt as u64));
+        if let Some(metrics) = MazeMetrics::of(maze) {
+            if target.accepts(&metrics) {
+                return Some(metrics);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_of_corridor() {
+        // A straight corridor with the treasure in the middle and a start at either end.
+        let mut maze = Maze::new(5, 1);
+        for x in 0..4 {
+            maze.remove_wall(x, 0, x + 1, 0);
+        }
+        maze.starts = vec![(0, 0), (4, 0)];
+        maze.treasure = (2, 0);
+        let metrics = MazeMetrics::of(&maze).unwrap();
+        assert_eq!(metrics.solution_length, 2);
+        assert_eq!(metrics.dead_ends, 2);
+        assert_eq!(metrics.branching_factor, 1.0);
+        assert_eq!(metrics.river_factor, 4.0);
+        assert_eq!(metrics.mean_start_distance, 2.0);
+        assert_eq!(metrics.start_distance_variance, 0.0);
+
+        maze.treasure = (1, 0);
+        let metrics = MazeMetrics::of(&maze).unwrap();
+        assert_eq!(metrics.mean_start_distance, 2.0);
+        assert_eq!(metrics.start_distance_variance, 1.0);
+    }
+
+    #[test]
+    fn test_generate_until_target() {
+        let mut maze = Maze::new(10, 10);
+        let target = DifficultyTarget { solution_length: (16, usize::MAX), ..Default::default() };
+        let metrics = generate_until_target(&mut maze, &target, 100, 200).unwrap();
+        assert!(metrics.solution_length >= 16);
+        assert_eq!(MazeMetrics::of(&maze), Some(metrics));
+
+        // The accepted maze can be rebuilt from its recorded seed.
+        let rebuilt = Maze::from_seed(10, 10, maze.seed.unwrap());
+        assert_eq!(rebuilt.grid, maze.grid);
+
+        let impossible = DifficultyTarget { solution_length: (1000, 1000), ..Default::default() };
+        assert!(generate_until_target(&mut maze, &impossible, 100, 5).is_none());
+    }
+}
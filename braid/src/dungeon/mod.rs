@@ -1,2 +1,16 @@
 pub mod maze;
+pub mod topology;
+pub mod levels;
+pub mod chunked;
+pub mod compact;
+pub mod visibility;
+pub mod merkle;
+pub mod commitment;
+pub mod generator;
+pub mod solver;
+pub mod metrics;
+pub mod render;
+pub mod export;
+pub mod format;
+pub mod scheduler;
 pub mod server;
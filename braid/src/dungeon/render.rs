@@ -0,0 +1,331 @@
+use crate::dungeon::maze::Maze;
+use crate::dungeon::topology::{Topology, MAX_SIDES};
+
+/*
+ * - Render Grid: The view of a maze the renderer needs, so the dungeon's maze and the player's
+ *   copy of it can share one renderer.
+ * - Overlay: What to draw on top of the walls: explored cells, players, starts, stairs and the
+ *   treasure.
+ * - Render: Draws a maze in ASCII to a String, north at the top. Square mazes take two text lines
+ *   per row, hex mazes two with odd rows shifted, and triangle mazes three.
+ */
+
+// Text drawn inside a cell, three characters wide.
+const EMPTY: &str = "   ";
+const UNEXPLORED: &str = "###";
+const PLAYER: &str = " @ ";
+const OTHER_PLAYER: &str = " P ";
+const START: &str = " S ";
+const TREASURE: &str = " T ";
+const STAIRS: &str = " = ";
+
+// A grid of cells with walls indexed by the sides of its topology, as seen by the renderer.
+pub trait RenderGrid {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn walls(&self, x: usize, y: usize) -> [bool; MAX_SIDES];
+
+    fn topology(&self) -> Topology {
+        Topology::Square
+    }
+}
+
+impl RenderGrid for Maze {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn walls(&self, x: usize, y: usize) -> [bool; MAX_SIDES] {
+        self.grid[x][y].walls
+    }
+
+    fn topology(&self) -> Topology {
+        self.topology
+    }
+}
+
+// Markers drawn over the maze.
+#[derive(Default)]
+pub struct Overlay<'a> {
+    pub mask: Option<&'a [Vec<bool>]>,      // Explored cells. Without a mask every cell is explored.
+    pub position: Option<(usize, usize)>,   // The viewing player's position.
+    pub others: Vec<(usize, usize)>,        // Positions of other players known to the viewer.
+    pub starts: Vec<(usize, usize)>,        // Start cells.
+    pub treasure: Option<(usize, usize)>,   // The treasure cell.
+    pub stairs: Vec<(usize, usize)>,        // Cells with stairs to another floor (see dungeon::levels).
+}
+
+impl<'a> Overlay<'a> {
+    // Overlay showing the starts and treasure of a dungeon maze.
+    pub fn for_maze(maze: &Maze) -> Self {
+        Overlay {
+            starts: maze.starts.clone(),
+            treasure: Some(maze.treasure),
+            ..Default::default()
+        }
+    }
+
+    // Get the text drawn inside a cell. Players take precedence over fixed markers, which take
+    // precedence over fog, since the starts and treasure are public and a view only lists the
+    // stairs that have been found.
+    fn content(&self, x: usize, y: usize) -> &'static str {
+        if self.position == Some((x, y)) {
+            PLAYER
+        } else if self.others.contains(&(x, y)) {
+            OTHER_PLAYER
+        } else if self.treasure == Some((x, y)) {
+            TREASURE
+        } else if self.starts.contains(&(x, y)) {
+            START
+        } else if self.stairs.contains(&(x, y)) {
+            STAIRS
+        } else if self.mask.is_some_and(|mask| !mask[x][y]) {
+            UNEXPLORED
+        } else {
+            EMPTY
+        }
+    }
+}
+
+// Decide whether to draw the wall between cells a and b. In a masked view the hidden cell keeps
+// all its walls, so when only one side is explored that side is trusted. Otherwise the wall is
+// drawn if either side has it.
+pub(crate) fn shared_wall(mask: Option<&[Vec<bool>]>, a: (usize, usize), a_wall: bool, b: (usize, usize), b_wall: bool) -> bool {
+    let explored = |(x, y): (usize, usize)| mask.is_none_or(|mask| mask[x][y]);
+    match (explored(a), explored(b)) {
+        (true, false) => a_wall,
+        (false, true) => b_wall,
+        _ => a_wall || b_wall,
+    }
+}
+
+// Render a maze with an overlay, with x running east and y running south.
+pub fn render<G: RenderGrid>(grid: &G, overlay: &Overlay) -> String {
+    match grid.topology() {
+        Topology::Square => render_square(grid, overlay),
+        topology => render_tiled(grid, topology, overlay),
+    }
+}
+
+// Render a square maze, with walls joined at '+' corners.
+fn render_square<G: RenderGrid>(grid: &G, overlay: &Overlay) -> String {
+    let (width, height) = (grid.width(), grid.height());
+    let mut out = String::new();
+
+    for y in 0..height {
+        // Top line of the row, made of the north walls.
+        for x in 0..width {
+            let north = if y == 0 {
+                grid.walls(x, y)[0]
+            } else {
+                shared_wall(overlay.mask, (x, y), grid.walls(x, y)[0], (x, y - 1), grid.walls(x, y - 1)[2])
+            };
+            out.push_str(if north { "+---" } else { "+   " });
+        }
+        out.push_str("+\n");
+
+        // Middle line of the row, made of the west walls and the cell contents.
+        for x in 0..width {
+            let west = if x == 0 {
+                grid.walls(x, y)[3]
+            } else {
+                shared_wall(overlay.mask, (x, y), grid.walls(x, y)[3], (x - 1, y), grid.walls(x - 1, y)[1])
+            };
+            out.push(if west { '|' } else { ' ' });
+            out.push_str(overlay.content(x, y));
+        }
+        out.push(if grid.walls(width - 1, y)[1] { '|' } else { ' ' });
+        out.push('\n');
+    }
+
+    // Bottom line of the maze, made of the south walls of the last row.
+    for x in 0..width {
+        out.push_str(if grid.walls(x, height - 1)[2] { "+---" } else { "+   " });
+    }
+    out.push_str("+\n");
+    out
+}
+
+// A character drawn at a line and column of the text.
+type Glyph = (usize, usize, char);
+
+// Get where the walls of a hex or triangle cell are drawn, as (line, column, glyph) per side, and
+// where its contents start.
+//
+// Hexagons are four columns wide, with odd rows shifted two columns east:
+//    / \ / \
+//   |   |   |
+//    \ / \ / \
+//     |   |   |
+//
+// Triangles are six columns wide and three lines tall, each starting three columns after the
+// previous one, with the bases drawn as underscores:
+//       ____
+//     /\    /
+//    /  \  /
+//   /____\/
+fn glyphs(topology: Topology, x: usize, y: usize) -> (Vec<Vec<Glyph>>, (usize, usize)) {
+    match topology {
+        Topology::Hex => {
+            let (line, left) = (2 * y, 4 * x + 2 * (y % 2));
+            let sides = vec![
+                vec![(line, left + 3, '\\')],
+                vec![(line + 1, left + 4, '|')],
+                vec![(line + 2, left + 3, '/')],
+                vec![(line + 2, left + 1, '\\')],
+                vec![(line + 1, left, '|')],
+                vec![(line, left + 1, '/')],
+            ];
+            (sides, (line + 1, left + 1))
+        }
+        _ => {
+            let (line, left) = (3 * y, 3 * x);
+            let base = |line: usize| (left + 1..left + 5).map(|col| (line, col, '_')).collect();
+            if (x + y).is_multiple_of(2) {
+                // Pointing up, with the base at the bottom.
+                let sides = vec![
+                    vec![(line + 1, left + 3, '\\'), (line + 2, left + 4, '\\'), (line + 3, left + 5, '\\')],
+                    base(line + 3),
+                    vec![(line + 1, left + 2, '/'), (line + 2, left + 1, '/'), (line + 3, left, '/')],
+                ];
+                (sides, (line + 2, left + 2))
+            } else {
+                // Pointing down, with the base at the top.
+                let sides = vec![
+                    vec![(line + 1, left + 5, '/'), (line + 2, left + 4, '/'), (line + 3, left + 3, '/')],
+                    base(line),
+                    vec![(line + 1, left, '\\'), (line + 2, left + 1, '\\'), (line + 3, left + 2, '\\')],
+                ];
+                (sides, (line + 1, left + 2))
+            }
+        }
+    }
+}
+
+// Render a hex or triangle maze by drawing every wall onto a grid of characters.
+fn render_tiled<G: RenderGrid>(grid: &G, topology: Topology, overlay: &Overlay) -> String {
+    let (width, height) = (grid.width(), grid.height());
+    let lines = if topology == Topology::Hex { 2 * height + 1 } else { 3 * height + 1 };
+    let mut canvas = vec![vec![' '; 4 * width + 4]; lines];
+
+    for x in 0..width {
+        for y in 0..height {
+            let walls = grid.walls(x, y);
+            let (sides, (line, left)) = glyphs(topology, x, y);
+            for (side, glyph) in sides.into_iter().enumerate() {
+                // Shared walls are drawn by both cells, which always agree on them.
+                let wall = match topology.neighbor(x, y, side, width, height) {
+                    Some(n) => shared_wall(overlay.mask, (x, y), walls[side], n, grid.walls(n.0, n.1)[topology.opposite(side)]),
+                    None => walls[side],
+                };
+                if wall {
+                    for (l, col, c) in glyph {
+                        canvas[l][col] = c;
+                    }
+                }
+            }
+            // Triangles only have room for the marker itself, without its padding.
+            let content = overlay.content(x, y);
+            let content = if topology == Topology::Triangle { content.trim() } else { content };
+            for (i, c) in content.chars().enumerate() {
+                canvas[line][left + i] = c;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for line in canvas {
+        let text: String = line.into_iter().collect();
+        out.push_str(text.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_walls_and_markers() {
+        // A 3x2 maze that is wider than it is tall, so a transposed render would be caught:
+        //   (0,0) (1,0) (2,0)
+        //   (0,1) (1,1) (2,1)
+        let mut maze = Maze::new(3, 2);
+        maze.remove_wall(0, 0, 1, 0);
+        maze.remove_wall(1, 0, 2, 0);
+        maze.remove_wall(2, 0, 2, 1);
+        maze.remove_wall(0, 0, 0, 1);
+        maze.starts = vec![(0, 1)];
+        maze.treasure = (2, 1);
+        let expected = "\
++---+---+---+
+|           |
++   +---+   +
+| S |   | T |
++---+---+---+
+";
+        assert_eq!(render(&maze, &Overlay::for_maze(&maze)), expected);
+    }
+
+    #[test]
+    fn test_render_masked_view_with_players() {
+        let mut maze = Maze::new(3, 2);
+        maze.remove_wall(0, 0, 1, 0);
+        maze.remove_wall(1, 0, 1, 1);
+        maze.remove_wall(1, 1, 2, 1);
+        let mask = vec![vec![true, false], vec![true, true], vec![false, false]];
+        let view = maze.get_masked_maze(&mask);
+        let overlay = Overlay {
+            mask: Some(&mask),
+            position: Some((1, 1)),
+            others: vec![(0, 0)],
+            ..Default::default()
+        };
+        // The passage from (1,1) into the unexplored (2,1) is still drawn as open.
+        let expected = "\
++---+---+---+
+| P     |###|
++---+   +---+
+|###| @  ###|
++---+---+---+
+";
+        assert_eq!(render(&view, &overlay), expected);
+    }
+
+    #[test]
+    fn test_render_hex_and_triangle() {
+        // Lines are listed separately since they start with spaces and contain backslashes.
+        let hex = [
+            r" / \ / \ / \",
+            r"|           |",
+            r" \ / \   \ / \",
+            r"  | S |   | T |",
+            r"   \ / \ / \ /",
+        ];
+        let triangle = [
+            r"    ____",
+            r"  /      \",
+            r" /        \",
+            r"/____  ____\",
+            r"\ S  /\ T  /",
+            r" \  /  \  /",
+            r"  \/____\/",
+        ];
+        for (topology, lines) in [(Topology::Hex, &hex[..]), (Topology::Triangle, &triangle[..])] {
+            let mut maze = Maze::new(3, 2);
+            maze.topology = topology;
+            maze.remove_wall(0, 0, 1, 0);
+            maze.remove_wall(1, 0, 2, 0);
+            maze.remove_wall(1, 0, 1, 1);
+            maze.starts = vec![(0, 1)];
+            maze.treasure = (2, 1);
+            let expected: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+            assert_eq!(render(&maze, &Overlay::for_maze(&maze)), expected, "{:?}", topology);
+        }
+    }
+}
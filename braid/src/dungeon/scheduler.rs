@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/*
+ * - Rounds: The game advances one round at a time. Every registered player gets exactly one action
+ *   per round, however fast their connection is, and a round's actions are resolved together.
+ * - Deadline: A round's deadline starts with its first action. Players who have not acted by then
+ *   miss the round, so one slow or idle player cannot hold up the others. A round nobody has
+ *   acted in does not close, so an idle game does not run down its turns.
+ * - Resting: A player whose last step took several turns, through costly terrain or a trap, sits
+ *   out that many rounds less one. Resting players are not waited for, and a round with only
+ *   resting players closes at once.
+ * - Deterministic Order: Actions are handed back sorted by player id, whatever order they came in,
+ *   so resolving a round gives the same result on every run.
+ */
+
+// Why an action was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    NotRegistered,              // The player has not joined the game.
+    Resting(usize),             // The player sits out this many more rounds.
+    AlreadyActed,               // The player already acted this round.
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::NotRegistered => write!(f, "player has not joined the game"),
+            ScheduleError::Resting(rounds) => write!(f, "player is resting for {} more rounds", rounds),
+            ScheduleError::AlreadyActed => write!(f, "player already acted this round"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+// The actions of a closed round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Round<A> {
+    pub number: usize,              // Number of the round, counting from 1.
+    pub actions: Vec<(usize, A)>,   // Actions by player id, in ascending order.
+    pub missed: Vec<usize>,         // Players who were expected to act but did not.
+}
+
+// Collects one action per player per round.
+#[derive(Clone, Debug)]
+pub struct Scheduler<A> {
+    round_length: Duration,         // Time from a round's first action to its deadline.
+    round: usize,                   // Number of rounds closed so far.
+    players: BTreeMap<usize, usize>, // Registered players, with the rounds each still sits out.
+    actions: BTreeMap<usize, A>,    // Actions taken in the current round, by player id.
+    deadline: Option<Instant>,      // When the current round closes, once someone has acted.
+}
+
+impl<A> Scheduler<A> {
+    // Create a scheduler giving each round the given time after its first action.
+    pub fn new(round_length: Duration) -> Self {
+        Scheduler { round_length, round: 0, players: BTreeMap::new(), actions: BTreeMap::new(), deadline: None }
+    }
+
+    // Number of rounds closed so far.
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    // Register a player, who is waited for from the current round on. Registering again keeps
+    // the player's rest.
+    pub fn register(&mut self, player_id: usize) {
+        self.players.entry(player_id).or_insert(0);
+    }
+
+    // Unregister a player, dropping any action they took this round.
+    pub fn unregister(&mut self, player_id: usize) {
+        self.players.remove(&player_id);
+        self.actions.remove(&player_id);
+    }
+
+    // Get the registered players, in ascending order of id.
+    pub fn players(&self) -> impl Iterator<Item = usize> + '_ {
+        self.players.keys().copied()
+    }
+
+    // Make a player sit out the given number of rounds after the current one.
+    pub fn rest(&mut self, player_id: usize, rounds: usize) {
+        if let Some(rest) = self.players.get_mut(&player_id) {
+            *rest = rounds;
+        }
+    }
+
+    // Take a player's action for the current round, starting the round's deadline if it is the
+    // first.
+    pub fn submit(&mut self, player_id: usize, action: A, now: Instant) -> Result<(), ScheduleError> {
+        match self.players.get(&player_id) {
+            None => return Err(ScheduleError::NotRegistered),
+            Some(&rest) if rest > 0 => return Err(ScheduleError::Resting(rest)),
+            Some(_) => {}
+        }
+        if self.actions.contains_key(&player_id) {
+            return Err(ScheduleError::AlreadyActed);
+        }
+        self.actions.insert(player_id, action);
+        self.deadline.get_or_insert(now + self.round_length);
+        Ok(())
+    }
+
+    // When the current round closes whatever happens, if anyone has acted in it.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Check whether the current round is ready to close: every player who is not resting has
+    // acted, or the deadline has passed.
+    pub fn is_due(&self, now: Instant) -> bool {
+        if self.players.is_empty() {
+            return false;
+        }
+        let everyone_acted = self.players.iter()
+            .filter(|&(_, &rest)| rest == 0)
+            .all(|(player_id, _)| self.actions.contains_key(player_id));
+        everyone_acted || self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+
+    // Close the current round, handing back its actions and starting the next.
+    pub fn close_round(&mut self) -> Round<A> {
+        self.round += 1;
+        self.deadline = None;
+        let actions: Vec<(usize, A)> = std::mem::take(&mut self.actions).into_iter().collect();
+        let mut missed = vec![];
+        for (&player_id, rest) in self.players.iter_mut() {
+            if *rest > 0 {
+                *rest -= 1;
+            } else if actions.binary_search_by_key(&player_id, |&(id, _)| id).is_err() {
+                missed.push(player_id);
+            }
+        }
+        Round { number: self.round, actions, missed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_waits_for_every_player() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(Duration::from_secs(10));
+        assert!(!scheduler.is_due(now));
+        for player_id in [3, 1, 2] {
+            scheduler.register(player_id);
+        }
+        assert_eq!(scheduler.submit(4, 'x', now), Err(ScheduleError::NotRegistered));
+        scheduler.submit(3, 'c', now).unwrap();
+        scheduler.submit(1, 'a', now).unwrap();
+        // A fast player cannot act twice in a round.
+        assert_eq!(scheduler.submit(3, 'd', now), Err(ScheduleError::AlreadyActed));
+        assert!(!scheduler.is_due(now));
+        scheduler.submit(2, 'b', now).unwrap();
+        assert!(scheduler.is_due(now));
+        let round = scheduler.close_round();
+        assert_eq!(round, Round { number: 1, actions: vec![(1, 'a'), (2, 'b'), (3, 'c')], missed: vec![] });
+        assert_eq!(scheduler.deadline(), None);
+        scheduler.submit(3, 'd', now).unwrap();
+    }
+
+    #[test]
+    fn test_deadline_closes_round_without_slow_players() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(Duration::from_secs(10));
+        scheduler.register(1);
+        scheduler.register(2);
+        // Nobody has acted, so the round stays open.
+        assert!(!scheduler.is_due(now + Duration::from_secs(60)));
+        scheduler.submit(2, 'b', now).unwrap();
+        assert_eq!(scheduler.deadline(), Some(now + Duration::from_secs(10)));
+        assert!(!scheduler.is_due(now + Duration::from_secs(9)));
+        assert!(scheduler.is_due(now + Duration::from_secs(10)));
+        let round = scheduler.close_round();
+        assert_eq!(round.actions, vec![(2, 'b')]);
+        assert_eq!(round.missed, vec![1]);
+
+        // A player who leaves is no longer waited for.
+        scheduler.submit(2, 'c', now).unwrap();
+        scheduler.unregister(1);
+        assert!(scheduler.is_due(now));
+    }
+
+    #[test]
+    fn test_resting_players_sit_out_rounds() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(Duration::from_secs(10));
+        scheduler.register(1);
+        scheduler.register(2);
+        scheduler.rest(1, 2);
+        assert_eq!(scheduler.submit(1, 'a', now), Err(ScheduleError::Resting(2)));
+        scheduler.submit(2, 'b', now).unwrap();
+        assert!(scheduler.is_due(now));
+        assert!(scheduler.close_round().missed.is_empty());
+
+        // With everyone resting the round closes at once.
+        scheduler.rest(2, 1);
+        assert!(scheduler.is_due(now));
+        let round = scheduler.close_round();
+        assert_eq!(round.number, 2);
+        assert!(round.actions.is_empty() && round.missed.is_empty());
+        assert!(!scheduler.is_due(now));
+        scheduler.submit(1, 'a', now).unwrap();
+        scheduler.submit(2, 'b', now).unwrap();
+    }
+}
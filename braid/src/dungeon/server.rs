@@ -1,107 +1,379 @@
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+use rand::Rng;
 use serde::{Serialize, Deserialize};
-use crate::dungeon::maze::{Maze, Cell};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use crate::dungeon::levels::{Direction, LayeredMaze, Position};
+use crate::dungeon::maze::KeySet;
+use crate::dungeon::generator::Algorithm;
+use crate::dungeon::visibility::Visibility;
+use crate::dungeon::merkle::{CellTree, Hash};
+use crate::dungeon::commitment::{Commitment, Opening};
+use crate::dungeon::scheduler::Scheduler;
+use crate::protocol::{self, Message, ProtocolError, View};
 use crate::blockchain::state_channel::{StateChannel, State};
-use secp256k1::{Secp256k1, SecretKey, Signature, PublicKey};
-
-/**
- * - Server Structure: Represents the server state with a shared maze and player data.
- * - New Server: Initializes the server with a generated maze.
- * - Add Player: Adds a new player to the server, initializing their exploration mask.
- * - Handle Client: Manages incoming player connections and processes their requests.
- * - Update Player Exploration: Updates the player's exploration mask.
- * - Get Player View: Returns the current view of the maze for the player based on their exploration mask.
+use secp256k1::{Signature, PublicKey};
+
+/*
+ * - Game Structure: The authoritative game state, owned by a single actor task.
+ * - Rounds: Each turn is a round of the turn scheduler (see scheduler), resolved all at once.
+ * - New Game: Initializes the game with a generated maze and commits to its layout, cells and solution.
+ * - Game From Maze: Sets up a game in a maze generated beforehand, such as a seeded one.
+ * - Welcome: Answers every Hello with the game's commitments before the player's first view.
+ * - Add Player: Adds a new player to the game at one of the maze's starts.
+ * - Handle Message: Applies one protocol message from a connection, returning the replies.
+ * - Resolve Round: Moves every player who acted this round and sends everyone their view.
+ * - Record Signature: Checks and keeps a player's signature over their state channel state.
+ * - Move Player: Moves the player one step from their tracked position.
+ * - Get Player View: Returns the proven view of the maze the player can see.
  * - Update Treasure: Updates the treasure amount based on the current turn.
- * - Start Server: Listens for incoming connections and handles them in separate threads.
- * - Main Function: Initializes and starts the server.
+ * - Reveal Solution: Returns the solution path and the maze with their openings at game end.
+ * - Game Actor: Runs the game state, taking requests from connections over a channel.
+ * - Handle Connection: Passes one player's messages to the game actor and writes out its replies.
+ * - Serve: Listens for incoming connections until the game ends or the server is interrupted.
  */
 
-// Structure to hold player data and their exploration mask.
+// Time players have to move in a round once its first move is in.
+const ROUND_LENGTH: Duration = Duration::from_secs(30);
+
+// Mazes generated for a new game before giving up on fitting the treasure in the turns allowed.
+const MAX_ATTEMPTS: usize = 100;
+
+// Why a game could not be set up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameError {
+    NoStarts,                   // The maze has no start for players to enter at.
+    Unsolvable,                 // No path leads from the first start to the treasure.
+    TooFewTurns {
+        solution_cost: usize,   // Turns the cheapest path to the treasure takes.
+        max_turns: usize,       // Turns the game allows.
+    },
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::NoStarts => write!(f, "the maze has no start cells"),
+            GameError::Unsolvable => write!(f, "the treasure cannot be reached"),
+            GameError::TooFewTurns { solution_cost, max_turns } => write!(f, "the treasure takes {} turns to reach, more than the {} allowed", solution_cost, max_turns),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+// Structure to hold player data and their exploration mask, indexed [z][x][y]. Kept by the
+// server alone, never taken from a request.
 #[derive(Serialize, Deserialize, Clone)]
 struct PlayerData {
     id: usize,
-    exploration_mask: Vec<Vec<bool>>,
-    commitment: Vec<u8>, // Commitment of the current position.
+    position: Position, // Cell the player is in.
+    start: Position, // Cell the player entered the maze at, and is dropped back at by pits.
+    keys: KeySet, // Keys the player has picked up.
+    exploration_mask: Vec<Vec<Vec<bool>>>,
+    visible_mask: Vec<Vec<Vec<bool>>>, // Cells seen from the explored cells.
+    commitment: Commitment, // Salted commitment of the player's exploration, opened by the player.
+    public_key: Vec<u8>, // Serialized key the player signs their state channel states with.
+}
+
+impl PlayerData {
+    // Mark a cell the player stepped into as explored, and bring what they can see from it into view.
+    fn explore(&mut self, maze: &LayeredMaze, visibility: Visibility, (x, y, z): Position) {
+        self.exploration_mask[z][x][y] = true;
+        let seen = visibility.visible_cells(&maze.floors[z], (x, y));
+        for (column, seen) in self.visible_mask[z].iter_mut().zip(seen) {
+            for (cell, seen) in column.iter_mut().zip(seen) {
+                *cell |= seen;
+            }
+        }
+    }
 }
 
-// Structure to represent the server state.
-struct Server {
-    maze: Arc<Mutex<Maze>>, // Shared maze between threads.
-    players: Arc<Mutex<Vec<PlayerData>>>, // Shared player data between threads.
-    state_channels: Arc<Mutex<HashMap<usize, StateChannel>>>, // State channels for each player.
+// A message from a connection for the game actor, with the channel to answer on.
+struct Request {
+    player_id: Option<usize>, // Player the connection first said hello as, if it has.
+    server_address: String, // Address the connection reached the server on.
+    message: Message,
+    outbox: mpsc::UnboundedSender<Message>, // Messages for the connection to send, in order.
+}
+
+// Commands the game actor takes.
+enum Command {
+    Request(Box<Request>), // A message from a connection.
+    Leave(usize, mpsc::UnboundedSender<Message>), // The connection of a player closed, with its outbox.
+    Shutdown, // End the game early, as the server is shutting down.
+}
+
+// Structure to represent the game state, owned by the game actor. Requests are applied one at a
+// time, so every connection sees the same turn and treasure.
+pub struct Game {
+    maze: LayeredMaze,
+    players: Vec<PlayerData>,
+    state_channels: HashMap<usize, StateChannel>, // State channels for each player.
+    layout_commitment: Commitment, // Salted commitment to the maze layout, including its starts and treasure.
+    layout_opening: Opening, // Opening of the layout commitment, kept secret until game end.
+    cell_trees: Vec<CellTree>, // Merkle trees over each floor's salted cells, kept secret.
+    cell_roots: Vec<Hash>, // Roots of the cell trees, published before the game.
+    scheduler: Scheduler<(Direction, Commitment)>, // Moves queued for the current round, by player.
+    solution_path: Vec<Position>, // Solution path from the start to the treasure, kept secret until game end.
+    solution_commitment: Commitment, // Salted commitment to the solution path, published before the game.
+    solution_opening: Opening, // Opening of the solution commitment, kept secret until game end.
+    solution_cost: usize, // Turns the solution path takes, at most max_turns.
+    visibility: Visibility, // What players can see from the cells they explore.
     max_turns: usize, // Maximum number of turns allowed.
     current_turn: usize, // Current turn number.
     initial_treasure: f64, // Initial treasure amount.
     treasure: f64, // Current treasure amount.
+    over: bool, // Whether the game has ended.
 }
 
-impl Server {
-    // Create a new server with a generated maze.
-    fn new(maze_width: usize, maze_height: usize, max_turns: usize, initial_treasure: f64) -> Self {
-        let mut maze = Maze::new(maze_width, maze_height);
-        maze.generate();
-        Server {
-            maze: Arc::new(Mutex::new(maze)),
-            players: Arc::new(Mutex::new(Vec::new())),
-            state_channels: Arc::new(Mutex::new(HashMap::new())),
+impl Game {
+    // Create a new game with a maze of the given number of floors, each generated by the given
+    // algorithm, generating up to MAX_ATTEMPTS mazes until one's treasure can be reached in the
+    // turns allowed.
+    pub fn new(maze_width: usize, maze_height: usize, maze_depth: usize, algorithm: Algorithm, visibility: Visibility, max_turns: usize, initial_treasure: f64) -> Result<Self, GameError> {
+        let mut result = Err(GameError::Unsolvable);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut maze = LayeredMaze::new(maze_width, maze_height, maze_depth);
+            for floor in maze.floors.iter_mut() {
+                floor.algorithm = algorithm;
+            }
+            maze.generate();
+            result = Game::from_maze(maze, visibility, max_turns, initial_treasure);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    // Create a new game in a generated maze, if its treasure can be reached in the turns allowed.
+    // Commits to the layout, to the Merkle root of every floor's salted cells and to a cheapest
+    // solution.
+    pub fn from_maze(maze: LayeredMaze, visibility: Visibility, max_turns: usize, initial_treasure: f64) -> Result<Self, GameError> {
+        let &start = maze.starts.first().ok_or(GameError::NoStarts)?;
+        let solution_path = maze.solution_path(start).ok_or(GameError::Unsolvable)?;
+        let solution_cost = maze.path_cost(&solution_path);
+        if solution_cost > max_turns {
+            return Err(GameError::TooFewTurns { solution_cost, max_turns });
+        }
+        let mut rng = rand::thread_rng();
+        let (solution_commitment, solution_opening) = maze.commit_solution_path(&solution_path, &mut rng);
+        let (layout_commitment, layout_opening) = maze.commit_layout(&mut rng);
+        let cell_trees: Vec<CellTree> = maze.floors.iter().map(|floor| CellTree::new(floor, rng.gen())).collect();
+        let cell_roots = cell_trees.iter().map(CellTree::root).collect();
+        Ok(Game {
+            maze,
+            players: Vec::new(),
+            state_channels: HashMap::new(),
+            layout_commitment,
+            layout_opening,
+            cell_trees,
+            cell_roots,
+            scheduler: Scheduler::new(ROUND_LENGTH),
+            solution_path,
+            solution_commitment,
+            solution_opening,
+            solution_cost,
+            visibility,
             max_turns,
             current_turn: 0,
             initial_treasure,
             treasure: initial_treasure,
-        }
+            over: false,
+        })
     }
 
-    // Add a new player to the server.
-    fn add_player(&self, player_id: usize, player_address: &str, server_address: &str) {
-        let maze = self.maze.lock().unwrap();
-        let exploration_mask = vec![vec![false; maze.height]; maze.width];
-        let player_data = PlayerData {
+    // Add a new player to the game, entering at the next of the maze's starts, of which from_maze
+    // makes sure there is one.
+    fn add_player(&mut self, player_id: usize, player_address: &str, server_address: &str, public_key: &[u8]) {
+        let maze = &self.maze;
+        let start = maze.starts[self.players.len() % maze.starts.len()];
+        let mut player_data = PlayerData {
             id: player_id,
-            exploration_mask,
-            commitment: vec![],
+            position: start,
+            start,
+            keys: maze.keys_at(start),
+            exploration_mask: vec![vec![vec![false; maze.height]; maze.width]; maze.depth],
+            visible_mask: vec![vec![vec![false; maze.height]; maze.width]; maze.depth],
+            commitment: Commitment::default(),
+            public_key: public_key.to_vec(),
         };
-        self.players.lock().unwrap().push(player_data);
-        self.state_channels.lock().unwrap().insert(player_id, StateChannel::new(player_address, server_address));
+        player_data.explore(maze, self.visibility, start);
+        self.players.push(player_data);
+        self.state_channels.insert(player_id, StateChannel::new(player_address, server_address));
     }
 
-    // Handle incoming player connections.
-    fn handle_client(&self, mut stream: TcpStream) {
-        let mut buffer = [0; 1024];
-        while let Ok(bytes_read) = stream.read(&mut buffer) {
-            if bytes_read == 0 {
-                return; // Connection was closed.
+    // Apply one message from a connection, received at the given time, returning the replies to
+    // send back. A Hello for a player who already joined needs the key they joined with, and is
+    // answered with the game's commitments and the player's view. Clients only send the direction
+    // they move in; a move is queued for the current round, and its result sent when the round is
+    // resolved.
+    fn handle_message(&mut self, player_id: Option<usize>, message: Message, server_address: &str, now: Instant) -> Vec<Message> {
+        if self.over {
+            return vec![];
+        }
+        match (message, player_id) {
+            (Message::Hello { id, address, public_key }, _) => {
+                match self.players.iter().find(|p| p.id == id) {
+                    None => self.add_player(id, &address, server_address, &public_key),
+                    // Anyone can claim an id, so only the key the player joined with gets it back.
+                    Some(player) if player.public_key != public_key => {
+                        return vec![Message::Error { message: format!("player {} joined with a different key", id) }];
+                    }
+                    Some(_) => {}
+                }
+                self.scheduler.register(id);
+                let welcome = Message::Welcome {
+                    cell_roots: self.cell_roots.clone(),
+                    layout_commitment: self.layout_commitment,
+                    solution_commitment: self.solution_commitment,
+                };
+                vec![welcome, Message::View(self.get_player_view(id))]
             }
-            let request: PlayerData = serde_json::from_slice(&buffer[..bytes_read]).unwrap();
-            self.update_player_exploration(&request);
-            let response = self.get_player_view(&request);
-            let response_json = serde_json::to_vec(&response).unwrap();
-            stream.write_all(&response_json).unwrap();
-
-            self.current_turn += 1;
-            self.update_treasure();
-            if self.current_turn >= self.max_turns {
-                println!("Max turns reached. Game over.");
-                break;
+            (Message::Move { direction, commitment }, Some(id)) => {
+                match self.scheduler.submit(id, (direction, commitment), now) {
+                    Ok(()) => vec![],
+                    Err(e) => vec![Message::Error { message: e.to_string() }],
+                }
+            }
+            (Message::Sign { state, signature }, Some(id)) => {
+                if self.record_signature(id, &state, &signature) {
+                    vec![]
+                } else {
+                    vec![Message::Error { message: "signature does not match the current state".to_string() }]
+                }
+            }
+            (Message::Move { .. } | Message::Sign { .. }, None) => {
+                vec![Message::Error { message: "say hello first".to_string() }]
             }
+            (_, _) => vec![Message::Error { message: "unexpected message".to_string() }],
+        }
+    }
+
+    // Resolve the current round: move every player who acted, in order of player id, and advance
+    // the turn. Returns the messages to send, by player: a challenge for each move made or an
+    // error for each move refused, then every player's new view. A step that takes several turns
+    // makes the player sit out the rounds it takes beyond the first. The game ends once a player
+    // reaches the treasure or the turns run out.
+    fn resolve_round(&mut self) -> Vec<(usize, Message)> {
+        let round = self.scheduler.close_round();
+        self.current_turn += 1;
+        self.update_treasure();
+        let mut messages = vec![];
+        for (id, (direction, commitment)) in round.actions {
+            // A blocked move still takes the round.
+            let Some(turns) = self.move_player(id, direction, commitment) else {
+                messages.push((id, Message::Error { message: format!("cannot move {:?} from where you are", direction) }));
+                continue;
+            };
+            self.scheduler.rest(id, turns.saturating_sub(1));
+            messages.push((id, Message::Challenge { state: self.record_move(id, commitment) }));
+        }
+        let players: Vec<usize> = self.scheduler.players().collect();
+        for id in players {
+            messages.push((id, Message::View(self.get_player_view(id))));
         }
+        if let Some(player) = self.players.iter().find(|p| p.position == self.maze.treasure) {
+            println!("Player {} reached the treasure. Game over.", player.id);
+            self.over = true;
+        } else if self.current_turn >= self.max_turns {
+            println!("Max turns reached. Game over.");
+            self.over = true;
+        }
+        messages
+    }
+
+    // Get the message announcing the end of the game, with the solution revealed.
+    fn game_over(&self) -> Message {
+        let (solution_path, opening) = self.reveal_solution();
+        println!("Solution path: {:?} ({} turns), opening {:?}", solution_path, self.solution_cost, opening.nonce);
+        Message::GameOver {
+            solution_path: solution_path.to_vec(),
+            opening,
+            solution_cost: self.solution_cost,
+            maze: self.maze.clone(),
+            layout_opening: self.layout_opening,
+            treasure: self.treasure,
+        }
+    }
+
+    // Move the player's state channel on to the state their move led to, returning it for the
+    // player to sign.
+    fn record_move(&mut self, player_id: usize, commitment: Commitment) -> State {
+        let channel = self.state_channels.get_mut(&player_id).expect("every player has a state channel");
+        channel.current_state = State {
+            player_address: channel.player_address.clone(),
+            move_hash: commitment.0.to_vec(),
+            turn_number: self.current_turn as u64,
+        };
+        channel.player_signature = None;
+        channel.current_state.clone()
     }
 
-    // Update the player's exploration mask based on their request.
-    fn update_player_exploration(&self, player_data: &PlayerData) {
-        let mut players = self.players.lock().unwrap();
-        if let Some(player) = players.iter_mut().find(|p| p.id == player_data.id) {
-            player.exploration_mask = player_data.exploration_mask.clone();
-            player.commitment = player_data.commitment.clone();
+    // Check a player's signature over their state channel's current state, keeping it if it holds.
+    fn record_signature(&mut self, player_id: usize, state: &State, signature: &[u8]) -> bool {
+        let Some(player) = self.players.iter().find(|p| p.id == player_id) else {
+            return false;
+        };
+        let (Ok(public_key), Ok(signature)) = (PublicKey::from_slice(&player.public_key), Signature::from_compact(signature)) else {
+            return false;
+        };
+        let Some(channel) = self.state_channels.get_mut(&player_id) else {
+            return false;
+        };
+        let current = &channel.current_state;
+        let same = state.player_address == current.player_address
+            && state.move_hash == current.move_hash
+            && state.turn_number == current.turn_number;
+        if !same || !channel.verify_state(state, &signature, &public_key) {
+            return false;
         }
+        channel.player_signature = Some(signature);
+        true
     }
 
-    // Get the current view of the maze for the player based on their exploration mask.
-    fn get_player_view(&self, player_data: &PlayerData) -> Maze {
-        let maze = self.maze.lock().unwrap();
-        maze.get_masked_maze(&player_data.exploration_mask)
+    // Move the player one step from their tracked position, checked against the walls, doors
+    // and one-way passages of the maze. Follows teleporters and pits, picks up keys and explores
+    // the cells stepped into. Returns the turns the step took, for its terrain and traps, or None
+    // if it was blocked.
+    fn move_player(&mut self, player_id: usize, direction: Direction, commitment: Commitment) -> Option<usize> {
+        let maze = &self.maze;
+        let player = self.players.iter_mut().find(|p| p.id == player_id)?;
+        let (entered, landed) = maze.step(player.position, direction, player.keys)?;
+        let (x, y, z) = entered;
+        let turns = maze.floors[z].step_cost(x, y);
+        player.explore(maze, self.visibility, entered);
+        // A pit leads nowhere on the floor, so the player starts over.
+        let landed = landed.unwrap_or(player.start);
+        player.explore(maze, self.visibility, landed);
+        player.position = landed;
+        player.keys |= maze.keys_at(landed);
+        player.commitment = commitment;
+        Some(turns)
+    }
+
+    // Get the current view of the maze for the player: the cells they can see from the cells they
+    // explored, each proven against its floor's committed root. What the client claims to see
+    // plays no part.
+    fn get_player_view(&self, player_id: usize) -> View {
+        let maze = &self.maze;
+        let hidden = vec![vec![vec![false; maze.height]; maze.width]; maze.depth];
+        let player = self.players.iter().find(|p| p.id == player_id);
+        let mask = player.map_or(&hidden, |player| &player.visible_mask);
+        // A game is only set up in a maze with a start, so there is one to show a stranger.
+        let position = player.map_or(maze.starts[0], |player| player.position);
+        let proofs = self.cell_trees.iter()
+            .zip(&maze.floors)
+            .zip(mask)
+            .map(|((tree, floor), mask)| tree.prove_mask(floor, mask))
+            .collect();
+        View { position, turn: self.current_turn, maze: maze.get_masked_maze(mask), proofs }
     }
 
     // Update the treasure amount based on the current turn.
@@ -113,42 +385,342 @@ impl Server {
         }
     }
 
-    // Start the server and listen for incoming connections.
-    fn start(&self, address: &str) {
-        let listener = TcpListener::bind(address).unwrap();
-        println!("Server listening on {}", address);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let server = self.clone();
-                    thread::spawn(move || {
-                        server.handle_client(stream);
-                    });
+    // Reveal the committed solution path with its opening, proving the maze was solvable. The game
+    // over message adds the whole maze and its layout opening.
+    fn reveal_solution(&self) -> (&[Position], Opening) {
+        (&self.solution_path, self.solution_opening)
+    }
+}
+
+// Run the game actor: apply each command in turn, and close rounds as they fall due or their
+// deadline passes, until every connection and the listener have let go of their senders. Each
+// connection gets its messages over its own outbox, which never blocks, so a slow connection
+// cannot hold up the game. The game over message is published once, when the game ends.
+async fn run_game(mut game: Game, mut commands: mpsc::Receiver<Command>, over: watch::Sender<Option<Message>>) {
+    let mut outboxes: HashMap<usize, mpsc::UnboundedSender<Message>> = HashMap::new();
+    loop {
+        // Without a deadline the sleep is never polled, so any instant will do.
+        let deadline = game.scheduler.deadline().unwrap_or_else(Instant::now);
+        let command = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => Some(command),
+                None => break,
+            },
+            _ = tokio::time::sleep_until(deadline.into()), if game.scheduler.deadline().is_some() => None,
+        };
+        match command {
+            Some(Command::Request(request)) => {
+                // Only the connection a player was last welcomed on speaks for them.
+                let welcomed = request.player_id.filter(|id| outboxes.get(id).is_some_and(|outbox| outbox.same_channel(&request.outbox)));
+                let replies = match request.message {
+                    Message::Hello { .. } => game.handle_message(request.player_id, request.message, &request.server_address, Instant::now()),
+                    message => game.handle_message(welcomed, message, &request.server_address, Instant::now()),
+                };
+                if let (Some(Message::Welcome { .. }), Some(id)) = (replies.first(), request.player_id) {
+                    outboxes.insert(id, request.outbox.clone());
                 }
-                Err(e) => {
-                    eprintln!("Connection failed: {}", e);
+                for message in replies {
+                    let _ = request.outbox.send(message); // The connection may have closed meanwhile.
                 }
             }
+            // A connection the player has since rejoined from, or never got in on, leaves them be.
+            Some(Command::Leave(id, outbox)) if outboxes.get(&id).is_some_and(|current| current.same_channel(&outbox)) => {
+                outboxes.remove(&id);
+                game.scheduler.unregister(id);
+            }
+            Some(Command::Leave(..)) => {}
+            Some(Command::Shutdown) => game.over = true,
+            None => {} // The round's deadline passed.
+        }
+        while !game.over && game.scheduler.is_due(Instant::now()) {
+            for (id, message) in game.resolve_round() {
+                if let Some(outbox) = outboxes.get(&id) {
+                    let _ = outbox.send(message);
+                }
+            }
+        }
+        if game.over && over.borrow().is_none() {
+            let _ = over.send(Some(game.game_over()));
         }
     }
 }
 
-// Implement Clone for Server to allow cloning within threads.
-impl Clone for Server {
-    fn clone(&self) -> Self {
-        Server {
-            maze: Arc::clone(&self.maze),
-            players: Arc::clone(&self.players),
-            state_channels: Arc::clone(&self.state_channels),
-            max_turns: self.max_turns,
-            current_turn: self.current_turn,
-            initial_treasure: self.initial_treasure,
-            treasure: self.treasure,
+// Read one frame from a connection and decode its message.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Message, ProtocolError> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header).await?;
+    let mut frame = vec![0; protocol::frame_len(header)?];
+    reader.read_exact(&mut frame).await?;
+    protocol::decode(&frame)
+}
+
+// Encode a message and write it to a connection as one frame.
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, message: &Message) -> Result<(), ProtocolError> {
+    writer.write_all(&protocol::encode(message)?).await?;
+    Ok(())
+}
+
+// Read messages from a connection and pass them to the game actor until the connection closes,
+// keeping track of the player it first said hello as.
+async fn read_requests<R: AsyncRead + Unpin>(reader: &mut R, player_id: &mut Option<usize>, server_address: &str, commands: &mpsc::Sender<Command>, outbox: &mpsc::UnboundedSender<Message>) {
+    loop {
+        let message = match read_frame(reader).await {
+            Ok(message) => message,
+            // The frame was read whole, so the stream is still in step and only the sender is told.
+            Err(e @ (ProtocolError::UnsupportedVersion(_) | ProtocolError::Malformed(_))) => {
+                let _ = outbox.send(Message::Error { message: e.to_string() });
+                continue;
+            }
+            Err(_) => return, // Connection was closed, or lost track of the frames.
+        };
+        if let Message::Hello { id, .. } = &message {
+            match *player_id {
+                Some(current) if current != *id => {
+                    let _ = outbox.send(Message::Error { message: format!("already said hello as player {}", current) });
+                    continue;
+                }
+                _ => *player_id = Some(*id),
+            }
+        }
+        let request = Box::new(Request { player_id: *player_id, server_address: server_address.to_string(), message, outbox: outbox.clone() });
+        if commands.send(Command::Request(request)).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Write the messages the game actor sends to a connection's outbox, in order, until the game ends.
+// Only waiting for a message races the end of the game, so a frame is never cut off halfway.
+async fn write_messages<W: AsyncWrite + Unpin>(writer: &mut W, inbox: &mut mpsc::UnboundedReceiver<Message>, over: &mut watch::Receiver<Option<Message>>) {
+    loop {
+        let message = tokio::select! {
+            message = inbox.recv() => message,
+            _ = over.changed() => None,
+        };
+        let Some(message) = message else {
+            return;
+        };
+        if write_frame(writer, &message).await.is_err() {
+            return;
         }
     }
 }
 
-fn main() {
-    let server = Server::new(10, 10, 100, 1000.0); // Initialize the server with a 10x10 maze, 100 max turns, and initial treasure of 1000.
-    server.start("127.0.0.1:7878"); // Start the server on localhost port 7878.
+// Handle one player's connection until it closes or the game ends. Reading and writing run side
+// by side, so the views of a round reach a player whether or not they have a message in flight.
+// The connection speaks for the player it first said hello as, once the game actor welcomes it.
+// Leaves the turn scheduler when the connection closes, and sends the game over message before
+// closing when the game ends.
+async fn handle_connection(stream: TcpStream, commands: mpsc::Sender<Command>, mut over: watch::Receiver<Option<Message>>) {
+    let server_address = stream.local_addr().map(|address| address.to_string()).unwrap_or_default();
+    let (mut reader, mut writer) = stream.into_split();
+    let (outbox, mut inbox) = mpsc::unbounded_channel();
+    let mut player_id = None; // Set by the player's first Hello.
+    tokio::select! {
+        _ = read_requests(&mut reader, &mut player_id, &server_address, &commands, &outbox) => {}
+        _ = write_messages(&mut writer, &mut inbox, &mut over) => {}
+    }
+    if let Some(id) = player_id {
+        let _ = commands.send(Command::Leave(id, outbox.clone())).await;
+    }
+    // Send what the last round left for the player before announcing the end of the game.
+    let game_over = over.borrow().clone();
+    if let Some(game_over) = game_over {
+        while let Ok(message) = inbox.try_recv() {
+            if write_frame(&mut writer, &message).await.is_err() {
+                return;
+            }
+        }
+        let _ = write_frame(&mut writer, &game_over).await;
+    }
+}
+
+// Serve the game on an address, running each connection as its own task, until it ends or the
+// server is interrupted with Ctrl-C, then wait for every connection to be told the game is over.
+pub async fn serve(game: Game, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    println!("Server listening on {}", address);
+    let (commands, receiver) = mpsc::channel(64);
+    let (over_sender, over) = watch::channel(None);
+    let actor = tokio::spawn(run_game(game, receiver, over_sender));
+    let mut connections = JoinSet::new();
+    let mut ended = over.clone();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(handle_connection(stream, commands.clone(), over.clone()));
+                }
+                Err(e) => {
+                    eprintln!("Connection failed: {}", e);
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                println!("Shutting down.");
+                let _ = commands.send(Command::Shutdown).await;
+                break;
+            }
+            _ = ended.changed() => break, // The game is over.
+        }
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+    drop(commands);
+    let _ = actor.await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::maze::{StartLayout, Terrain};
+    use crate::dungeon::scheduler::ScheduleError;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    // A game in a seeded maze, with turns to spare.
+    fn sample_game(maze: LayeredMaze) -> Game {
+        Game::from_maze(maze, Visibility::default(), 1000, 100.0).unwrap()
+    }
+
+    // Say hello as a player with a fresh key, returning the key and the replies.
+    fn join(game: &mut Game, id: usize) -> (SecretKey, Vec<Message>) {
+        let (secret_key, public_key) = Secp256k1::new().generate_keypair(&mut secp256k1::rand::thread_rng());
+        let hello = Message::Hello { id, address: format!("player{}", id), public_key: public_key.serialize().to_vec() };
+        (secret_key, game.handle_message(Some(id), hello, "server", Instant::now()))
+    }
+
+    // Find a direction that leads out of a cell, with the cell it enters.
+    fn open_step(maze: &LayeredMaze, from: Position) -> (Direction, Position) {
+        (0..maze.floors[from.2].topology.sides())
+            .map(Direction::Side)
+            .chain([Direction::Up, Direction::Down])
+            .find_map(|direction| maze.step(from, direction, 0).map(|(entered, _)| (direction, entered)))
+            .unwrap()
+    }
+
+    // Queue a move for a player, with a commitment nobody checks here.
+    fn submit(game: &mut Game, id: usize, direction: Direction, now: Instant) -> Vec<Message> {
+        game.handle_message(Some(id), Message::Move { direction, commitment: Commitment([id as u8; 32]) }, "server", now)
+    }
+
+    #[test]
+    fn test_hello_move_sign_flow() {
+        let mut game = sample_game(LayeredMaze::from_seed(6, 5, 2, 11));
+        let now = Instant::now();
+        assert!(matches!(&submit(&mut game, 1, Direction::Up, now)[..], [Message::Error { .. }]));
+        // Errors from a client are not echoed back as the server's own words.
+        let echo = game.handle_message(None, Message::Error { message: "anything".into() }, "server", now);
+        assert!(matches!(&echo[..], [Message::Error { message }] if message == "unexpected message"));
+        let (secret_key, replies) = join(&mut game, 1);
+        let [Message::Welcome { cell_roots, .. }, Message::View(view)] = &replies[..] else {
+            panic!("expected a welcome and a view, got {:?}", replies);
+        };
+        assert!(view.verify(cell_roots));
+        // Nobody else gets the player back without their key.
+        assert!(matches!(&join(&mut game, 1).1[..], [Message::Error { .. }]));
+
+        let start = game.players[0].position;
+        let (direction, entered) = open_step(&game.maze, start);
+        assert!(submit(&mut game, 1, direction, now).is_empty());
+        assert!(matches!(&submit(&mut game, 1, direction, now)[..], [Message::Error { .. }]));
+        assert!(game.scheduler.is_due(now));
+        let messages = game.resolve_round();
+        let [(1, Message::Challenge { state }), (1, Message::View(view))] = &messages[..] else {
+            panic!("expected a challenge and a view, got {:?}", messages);
+        };
+        assert_ne!(view.position, start);
+        assert!(game.players[0].exploration_mask[entered.2][entered.0][entered.1]);
+        assert_eq!(state.turn_number, 1);
+
+        // Only a signature with the player's key over the challenged state is kept.
+        let channel = game.state_channels[&1].clone();
+        let signature = channel.clone().sign_state(&secret_key).serialize_compact().to_vec();
+        let (other_key, _) = Secp256k1::new().generate_keypair(&mut secp256k1::rand::thread_rng());
+        let forged = channel.clone().sign_state(&other_key).serialize_compact().to_vec();
+        let sign = |signature: Vec<u8>| Message::Sign { state: state.clone(), signature };
+        assert!(matches!(&game.handle_message(Some(1), sign(forged), "server", now)[..], [Message::Error { .. }]));
+        assert!(game.handle_message(Some(1), sign(signature), "server", now).is_empty());
+        assert!(game.state_channels[&1].player_signature.is_some());
+    }
+
+    #[test]
+    fn test_costly_steps_rest_and_missed_deadlines_skip() {
+        let mut maze = LayeredMaze::from_seed(6, 5, 2, 11);
+        let start = maze.starts[0];
+        let (direction, (x, y, z)) = open_step(&maze, start);
+        maze.floors[z].grid[x][y].terrain = Terrain::Rubble;
+        maze.floors[z].grid[x][y].feature = None;
+        let mut game = sample_game(maze);
+        join(&mut game, 1);
+        join(&mut game, 2);
+        let now = Instant::now();
+
+        // Player 2 misses the deadline, so the round closes without them and they stay put.
+        let position = game.players[1].position;
+        submit(&mut game, 1, direction, now);
+        assert!(!game.scheduler.is_due(now));
+        assert!(game.scheduler.is_due(now + ROUND_LENGTH));
+        let messages = game.resolve_round();
+        assert!(!messages.iter().any(|(id, message)| *id == 2 && matches!(message, Message::Challenge { .. })));
+        assert_eq!(game.players[1].position, position);
+
+        // Rubble takes four turns, so player 1 sits out the next three rounds.
+        assert_eq!(game.players[0].position, (x, y, z));
+        let resting = ScheduleError::Resting(3).to_string();
+        assert!(matches!(&submit(&mut game, 1, direction, now)[..], [Message::Error { message }] if *message == resting));
+        let (direction, _) = open_step(&game.maze, position);
+        submit(&mut game, 2, direction, now);
+        assert!(game.scheduler.is_due(now));
+    }
+
+    #[test]
+    fn test_treasure_ends_the_game() {
+        let mut game = sample_game(LayeredMaze::from_seed(6, 5, 2, 11));
+        join(&mut game, 1);
+        let maze = &game.maze;
+        let path = &game.solution_path;
+        let before = path[path.len() - 2];
+        let direction = (0..maze.floors[before.2].topology.sides())
+            .map(Direction::Side)
+            .chain([Direction::Up, Direction::Down])
+            .find(|&direction| maze.step(before, direction, KeySet::MAX).and_then(|(_, landed)| landed) == Some(maze.treasure))
+            .unwrap();
+        game.players[0].position = before;
+        game.players[0].keys = KeySet::MAX;
+
+        // A move that goes nowhere is refused, but the round still passes.
+        let blocked = (0..maze.floors[before.2].topology.sides())
+            .map(Direction::Side)
+            .chain([Direction::Up, Direction::Down])
+            .find(|&direction| maze.step(before, direction, KeySet::MAX).is_none())
+            .unwrap();
+        submit(&mut game, 1, blocked, Instant::now());
+        assert!(matches!(&game.resolve_round()[..], [(1, Message::Error { .. }), (1, Message::View(_))]));
+        assert!(!game.over);
+
+        submit(&mut game, 1, direction, Instant::now());
+        game.resolve_round();
+        assert!(game.over);
+        // Once the game is over, messages get no answer.
+        assert!(submit(&mut game, 1, Direction::Up, Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn test_game_needs_turns_to_reach_the_treasure() {
+        let maze = LayeredMaze::from_seed(6, 5, 2, 7);
+        let cost = maze.path_cost(&maze.solution_path(maze.starts[0]).unwrap());
+        let refused = Game::from_maze(maze.clone(), Visibility::default(), cost - 1, 100.0);
+        assert_eq!(refused.err(), Some(GameError::TooFewTurns { solution_cost: cost, max_turns: cost - 1 }));
+        assert!(Game::from_maze(maze, Visibility::default(), cost, 100.0).is_ok());
+        // Nor can a game be set up in a maze nobody can enter.
+        let mut closed = LayeredMaze::new(6, 5, 2);
+        closed.floors[0].start_layout = StartLayout::PerPlayer(0);
+        closed.floors[0].doors = 2;
+        closed.generate_with_seed(7);
+        assert!(closed.starts.is_empty());
+        assert_eq!(Game::from_maze(closed, Visibility::default(), 1000, 100.0).err(), Some(GameError::NoStarts));
+        // A new game generates mazes until one fits, and gives up when none can.
+        assert!(Game::new(6, 5, 2, Algorithm::default(), Visibility::default(), 200, 100.0).is_ok());
+        assert!(matches!(Game::new(6, 5, 2, Algorithm::default(), Visibility::default(), 1, 100.0), Err(GameError::TooFewTurns { .. })));
+    }
 }
@@ -0,0 +1,316 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::{Feature, KeySet, Maze};
+
+/*
+ * - Solver: Selects the search used to find a shortest path between two cells.
+ * - BFS: Breadth-first search. Shortest in number of steps.
+ * - Dijkstra: Uniform cost search. Cheapest in total turns, each step costing the terrain of the
+ *   cell stepped into plus the turns a trap there holds a player for.
+ * - A*: Dijkstra guided by the topology's distance to the goal, or to a teleporter, visiting fewer cells.
+ * - All Shortest Paths: Every shortest path between two cells, for braided mazes with several routes.
+ *
+ * The searches run over (cell, keys held) states, so a path may detour to collect the key to a
+ * locked door and come back through cells it already passed. They follow the maze's moves, so
+ * one-way passages are only taken forwards, stepping into a teleporter lands on its pair, which
+ * is the next cell of the path, and pits are avoided.
+ */
+
+// A cell together with the keys held on reaching it.
+type State = (usize, usize, KeySet);
+
+// Cost of the cheapest step, into a plain corridor.
+const STEP_COST: usize = 1;
+
+// Search strategies for finding a shortest path through a maze.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Solver {
+    Bfs,
+    Dijkstra,
+    AStar,
+}
+
+impl Solver {
+    // Find a shortest path from start to goal, inclusive of both. None if goal is unreachable.
+    pub fn solve(&self, maze: &Maze, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        match self {
+            Solver::Bfs => bfs(maze, start, goal),
+            Solver::Dijkstra => dijkstra(maze, start, goal),
+            Solver::AStar => astar(maze, start, goal),
+        }
+    }
+}
+
+// Get the state a search starts from, holding any key lying in the start cell.
+fn initial(maze: &Maze, (x, y): (usize, usize)) -> State {
+    (x, y, maze.keys_at(x, y))
+}
+
+// Get the states reachable in one step, picking up the key in the cell landed in, each with the
+// cost of the cell stepped into.
+fn successors(maze: &Maze, (x, y, keys): State) -> Vec<(State, usize)> {
+    maze.passable_neighbors(x, y, keys)
+        .into_iter()
+        .filter_map(|(nx, ny)| {
+            let (lx, ly) = maze.landing(nx, ny)?;
+            Some(((lx, ly, keys | maze.keys_at(lx, ly)), maze.step_cost(nx, ny)))
+        })
+        .collect()
+}
+
+// Walk the parent links back from the goal state to rebuild the path.
+fn reconstruct(parents: &HashMap<State, State>, goal: State) -> Vec<(usize, usize)> {
+    let mut path = vec![(goal.0, goal.1)];
+    let mut current = goal;
+    while let Some(&parent) = parents.get(&current) {
+        path.push((parent.0, parent.1));
+        current = parent;
+    }
+    path.reverse();
+    path
+}
+
+// Breadth-first search from start to goal.
+pub fn bfs(maze: &Maze, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+    let first = initial(maze, start);
+    let mut parents = HashMap::new();
+    let mut seen = HashSet::from([first]);
+    let mut queue = VecDeque::from([first]);
+
+    while let Some(state) = queue.pop_front() {
+        if (state.0, state.1) == goal {
+            return Some(reconstruct(&parents, state));
+        }
+        for (next, _) in successors(maze, state) {
+            if seen.insert(next) {
+                parents.insert(next, state);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+// Dijkstra's algorithm from start to goal.
+pub fn dijkstra(maze: &Maze, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+    best_first(maze, start, goal, |_| 0)
+}
+
+// A* search from start to goal using the topology's distance, which never overestimates the
+// remaining cost since it counts at most the steps left and every step costs at least STEP_COST.
+// A teleporter can jump most of the way, so the distance is capped by the distance to the nearest
+// teleporter plus the shortest distance from any teleporter's far end to the goal.
+pub fn astar(maze: &Maze, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+    let teleporters: Vec<(usize, usize)> = maze.grid.iter()
+        .flatten()
+        .filter(|cell| matches!(cell.feature, Some(Feature::Teleporter { .. })))
+        .map(|cell| (cell.x, cell.y))
+        .collect();
+    let from_far_end = teleporters.iter()
+        .filter_map(|&(x, y)| maze.landing(x, y))
+        .map(|end| maze.topology.distance(end, goal))
+        .min();
+    best_first(maze, start, goal, |cell| {
+        let direct = maze.topology.distance(cell, goal);
+        let jump = from_far_end.map(|rest| {
+            teleporters.iter().map(|&teleporter| maze.topology.distance(cell, teleporter)).min().unwrap() + rest
+        });
+        direct.min(jump.unwrap_or(usize::MAX)) * STEP_COST
+    })
+}
+
+// Best-first search ordered by cost so far plus the heuristic. A zero heuristic is Dijkstra.
+fn best_first<H>(maze: &Maze, start: (usize, usize), goal: (usize, usize), heuristic: H) -> Option<Vec<(usize, usize)>>
+where
+    H: Fn((usize, usize)) -> usize,
+{
+    let first = initial(maze, start);
+    let mut parents = HashMap::new();
+    let mut costs = HashMap::from([(first, 0)]);
+    let mut open = BinaryHeap::new();
+    open.push(Reverse((heuristic(start), first)));
+
+    while let Some(Reverse((_, state))) = open.pop() {
+        if (state.0, state.1) == goal {
+            return Some(reconstruct(&parents, state));
+        }
+        for (next, step_cost) in successors(maze, state) {
+            let cost = costs[&state] + step_cost;
+            if costs.get(&next).is_none_or(|&known| cost < known) {
+                costs.insert(next, cost);
+                parents.insert(next, state);
+                open.push(Reverse((cost + heuristic((next.0, next.1)), next)));
+            }
+        }
+    }
+    None
+}
+
+// Find every shortest path from start to goal, stopping after limit paths since a heavily
+// braided maze can have exponentially many. Doors and features are ignored, since this describes
+// the routes of the layout rather than the moves of a player holding particular keys.
+pub fn all_shortest_paths(maze: &Maze, start: (usize, usize), goal: (usize, usize), limit: usize) -> Vec<Vec<(usize, usize)>> {
+    // Distance of every cell from the start.
+    let mut distances = vec![vec![usize::MAX; maze.height]; maze.width];
+    let mut queue = VecDeque::new();
+    distances[start.0][start.1] = 0;
+    queue.push_back(start);
+    while let Some((x, y)) = queue.pop_front() {
+        for (nx, ny) in maze.open_neighbors(x, y) {
+            if distances[nx][ny] == usize::MAX {
+                distances[nx][ny] = distances[x][y] + 1;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    let mut paths = vec![];
+    if distances[goal.0][goal.1] == usize::MAX {
+        return paths;
+    }
+
+    // Walk back from the goal, only ever stepping to a cell one closer to the start.
+    let mut stack = vec![vec![goal]];
+    while let Some(partial) = stack.pop() {
+        if paths.len() >= limit {
+            break;
+        }
+        let (x, y) = *partial.last().unwrap();
+        if (x, y) == start {
+            let mut path = partial;
+            path.reverse();
+            paths.push(path);
+            continue;
+        }
+        for (nx, ny) in maze.open_neighbors(x, y) {
+            if distances[nx][ny] + 1 == distances[x][y] {
+                let mut next = partial.clone();
+                next.push((nx, ny));
+                stack.push(next);
+            }
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::maze::Terrain;
+
+    // Check that a path is made of steps between adjacent, open cells.
+    fn is_valid_path(maze: &Maze, path: &[(usize, usize)]) -> bool {
+        path.windows(2).all(|step| maze.open_neighbors(step[0].0, step[0].1).contains(&step[1]))
+    }
+
+    #[test]
+    fn test_solvers_agree_on_shortest_path() {
+        let maze = Maze::from_seed(11, 9, 21);
+        let goal = maze.treasure;
+        let bfs_path = Solver::Bfs.solve(&maze, (0, 0), goal).unwrap();
+        assert_eq!(bfs_path.first(), Some(&(0, 0)));
+        assert_eq!(bfs_path.last(), Some(&goal));
+        assert!(is_valid_path(&maze, &bfs_path));
+        for solver in [Solver::Dijkstra, Solver::AStar] {
+            let path = solver.solve(&maze, (0, 0), goal).unwrap();
+            assert!(is_valid_path(&maze, &path));
+            assert_eq!(path.len(), bfs_path.len());
+        }
+    }
+
+    #[test]
+    fn test_all_shortest_paths_in_braided_maze() {
+        let mut maze = Maze::new(10, 10);
+        maze.braid = 1.0;
+        maze.generate_with_seed(4);
+        let goal = maze.treasure;
+        let shortest = bfs(&maze, (0, 0), goal).unwrap();
+        let paths = all_shortest_paths(&maze, (0, 0), goal, 64);
+        assert!(!paths.is_empty());
+        for path in &paths {
+            assert_eq!(path.len(), shortest.len());
+            assert!(is_valid_path(&maze, path));
+        }
+        let mut distinct = paths.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), paths.len());
+    }
+
+    #[test]
+    fn test_paths_collect_keys_before_doors() {
+        // A corridor with the key behind the start and a door in front of it:
+        //   key (0,0) - start (1,0) - door - (2,0) - treasure (3,0)
+        let mut maze = Maze::new(4, 1);
+        for x in 0..3 {
+            maze.remove_wall(x, 0, x + 1, 0);
+        }
+        maze.set_door(1, 0, 2, 0, Some(3));
+        assert!(bfs(&maze, (1, 0), (3, 0)).is_none());
+
+        maze.grid[0][0].key = Some(3);
+        let expected = vec![(1, 0), (0, 0), (1, 0), (2, 0), (3, 0)];
+        for solver in [Solver::Bfs, Solver::Dijkstra, Solver::AStar] {
+            assert_eq!(solver.solve(&maze, (1, 0), (3, 0)), Some(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn test_paths_follow_features() {
+        // An open corridor from (0,0) to (5,0), with a one-way passage out of (1,0) east, so it
+        // can only be walked west to east.
+        let mut maze = Maze::new(6, 1);
+        for x in 0..5 {
+            maze.remove_wall(x, 0, x + 1, 0);
+        }
+        maze.grid[1][0].one_way[1] = true;
+        assert!(bfs(&maze, (5, 0), (0, 0)).is_none());
+        assert_eq!(bfs(&maze, (0, 0), (5, 0)).map(|path| path.len()), Some(6));
+
+        // A teleporter in (1,0) paired with (4,0) jumps most of the way.
+        maze.grid[1][0].feature = Some(Feature::Teleporter { x: 4, y: 0 });
+        maze.grid[4][0].feature = Some(Feature::Teleporter { x: 1, y: 0 });
+        let expected = vec![(0, 0), (4, 0), (5, 0)];
+        for solver in [Solver::Bfs, Solver::Dijkstra, Solver::AStar] {
+            assert_eq!(solver.solve(&maze, (0, 0), (5, 0)), Some(expected.clone()));
+        }
+
+        // A pit blocks the corridor, and a trap makes it cost more.
+        maze.grid[1][0].feature = None;
+        maze.grid[4][0].feature = Some(Feature::Pit);
+        assert!(bfs(&maze, (0, 0), (5, 0)).is_none());
+        maze.grid[4][0].feature = Some(Feature::Trap { turns: 2 });
+        assert_eq!(dijkstra(&maze, (0, 0), (5, 0)).map(|path| path.len()), Some(6));
+    }
+
+    #[test]
+    fn test_paths_avoid_rough_terrain() {
+        // A fully open 3x2 room with rubble in the middle of the top row. Going round it through
+        // the bottom row takes more steps but fewer turns.
+        let mut maze = Maze::new(3, 2);
+        for x in 0..3 {
+            maze.remove_wall(x, 0, x, 1);
+            for y in 0..2 {
+                maze.remove_wall(x, y, x + 1, y);
+            }
+        }
+        maze.grid[1][0].terrain = Terrain::Rubble;
+        let direct = bfs(&maze, (0, 0), (2, 0)).unwrap();
+        assert_eq!(maze.path_cost(&direct), Terrain::Rubble.cost() + 1);
+        for solver in [Solver::Dijkstra, Solver::AStar] {
+            let path = solver.solve(&maze, (0, 0), (2, 0)).unwrap();
+            assert_eq!(path, vec![(0, 0), (0, 1), (1, 1), (2, 1), (2, 0)]);
+            assert_eq!(maze.path_cost(&path), 4);
+        }
+    }
+
+    #[test]
+    fn test_unreachable_goal() {
+        // Without generation every wall is intact.
+        let maze = Maze::new(4, 4);
+        assert!(bfs(&maze, (0, 0), (3, 3)).is_none());
+        assert!(astar(&maze, (0, 0), (3, 3)).is_none());
+        assert!(all_shortest_paths(&maze, (0, 0), (3, 3), 8).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Serialize, Deserialize};
+use crate::dungeon::commitment::Encode;
+
+/*
+ * - Topology: The tiling a maze is laid out on. Every topology addresses its cells as a
+ *   width x height grid, so masks, views and storage work the same for all of them.
+ * - Square: Sides [north, east, south, west].
+ * - Hex: Pointy-top hexagons with every odd row shifted half a cell east. Sides
+ *   [north east, east, south east, south west, west, north west].
+ * - Triangle: Triangles alternating between pointing up, when x + y is even, and down. Sides
+ *   [east, base, west], where the base is the south side of an up triangle and the north side
+ *   of a down triangle.
+ * - Geometry: Cell outlines in units of one cell width, for drawing.
+ */
+
+// Most sides any topology has. Cells always store this many walls; sides past the topology's
+// own count stay walled.
+pub const MAX_SIDES: usize = 6;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Topology {
+    #[default]
+    Square,
+    Hex,
+    Triangle,
+}
+
+// As a stable id, so commitments do not depend on how the enum is laid out.
+impl Encode for Topology {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Topology::Square => 0,
+            Topology::Hex => 1,
+            Topology::Triangle => 2,
+        });
+    }
+}
+
+impl Topology {
+    // Number of sides, and so of walls, of a cell.
+    pub fn sides(&self) -> usize {
+        match self {
+            Topology::Square => 4,
+            Topology::Hex => 6,
+            Topology::Triangle => 3,
+        }
+    }
+
+    // The side of the neighbor that faces back across the given side.
+    pub fn opposite(&self, side: usize) -> usize {
+        match self {
+            Topology::Square => (side + 2) % 4,
+            Topology::Hex => (side + 3) % 6,
+            Topology::Triangle => 2 - side,
+        }
+    }
+
+    // Get the neighbor across a side of a cell, if it lies within a width x height grid.
+    pub fn neighbor(&self, x: usize, y: usize, side: usize, width: usize, height: usize) -> Option<(usize, usize)> {
+        if side >= self.sides() {
+            return None;
+        }
+        let (dx, dy): (isize, isize) = match self {
+            Topology::Square => [(0, -1), (1, 0), (0, 1), (-1, 0)][side],
+            Topology::Hex => {
+                // Odd rows are shifted east, so their diagonal neighbors are one column further east.
+                let shift = (y % 2) as isize;
+                [(shift, -1), (1, 0), (shift, 1), (shift - 1, 1), (-1, 0), (shift - 1, -1)][side]
+            }
+            Topology::Triangle => {
+                let base = if (x + y).is_multiple_of(2) { 1 } else { -1 };
+                [(1, 0), (0, base), (-1, 0)][side]
+            }
+        };
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+            return None;
+        }
+        Some((nx as usize, ny as usize))
+    }
+
+    // Count the pairs of neighboring cells in a width x height grid without visiting them, or
+    // None if the count does not fit in a usize.
+    pub fn edges(&self, width: usize, height: usize) -> Option<usize> {
+        if width == 0 || height == 0 {
+            return Some(0);
+        }
+        let across = (width - 1).checked_mul(height)?;
+        let between_rows = match self {
+            Topology::Square => width.checked_mul(height - 1)?,
+            // Every cell but one at the end of the row has two neighbors in the next row.
+            Topology::Hex => (width.checked_mul(2)? - 1).checked_mul(height - 1)?,
+            // Only up triangles have a neighbor in the next row: the even columns of even rows
+            // and the odd columns of odd rows.
+            Topology::Triangle => width.div_ceil(2).checked_mul(height / 2)?.checked_add((width / 2).checked_mul((height - 1) / 2)?)?,
+        };
+        across.checked_add(between_rows)
+    }
+
+    // A lower bound on the number of steps between two cells, used as the A* heuristic.
+    pub fn distance(&self, a: (usize, usize), b: (usize, usize)) -> usize {
+        match self {
+            // Every step changes a single coordinate by one.
+            Topology::Square | Topology::Triangle => a.0.abs_diff(b.0) + a.1.abs_diff(b.1),
+            Topology::Hex => {
+                // Convert the offset coordinates to axial coordinates, where hex distance is exact.
+                let axial = |(x, y): (usize, usize)| (x as isize - (y as isize - (y as isize & 1)) / 2, y as isize);
+                let ((q1, r1), (q2, r2)) = (axial(a), axial(b));
+                let (dq, dr) = (q1 - q2, r1 - r2);
+                ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as usize
+            }
+        }
+    }
+
+    // Outline of a cell, with side i running from vertex i to vertex i + 1.
+    pub fn polygon(&self, x: usize, y: usize) -> Vec<(f64, f64)> {
+        let (x, y) = (x as f64, y as f64);
+        match self {
+            Topology::Square => vec![(x, y), (x + 1.0, y), (x + 1.0, y + 1.0), (x, y + 1.0)],
+            Topology::Hex => {
+                let r = 1.0 / 3f64.sqrt(); // Circumradius of a hexagon one unit wide.
+                let cx = x + 0.5 + 0.5 * (y as usize % 2) as f64;
+                let cy = r + y * 1.5 * r;
+                vec![
+                    (cx, cy - r),
+                    (cx + 0.5, cy - r / 2.0),
+                    (cx + 0.5, cy + r / 2.0),
+                    (cx, cy + r),
+                    (cx - 0.5, cy + r / 2.0),
+                    (cx - 0.5, cy - r / 2.0),
+                ]
+            }
+            Topology::Triangle => {
+                let h = 3f64.sqrt() / 2.0; // Height of a triangle one unit wide.
+                let (left, top, bottom) = (x * 0.5, y * h, (y + 1.0) * h);
+                if (x as usize + y as usize).is_multiple_of(2) {
+                    vec![(left + 0.5, top), (left + 1.0, bottom), (left, bottom)]
+                } else {
+                    vec![(left + 0.5, bottom), (left + 1.0, top), (left, top)]
+                }
+            }
+        }
+    }
+
+    // Size of a width x height grid when drawn, in units of one cell width.
+    pub fn extent(&self, width: usize, height: usize) -> (f64, f64) {
+        let (w, h) = (width as f64, height as f64);
+        match self {
+            Topology::Square => (w, h),
+            Topology::Hex => {
+                let r = 1.0 / 3f64.sqrt();
+                (w + if height > 1 { 0.5 } else { 0.0 }, r * (1.5 * h + 0.5))
+            }
+            Topology::Triangle => ((w + 1.0) * 0.5, h * 3f64.sqrt() / 2.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbors_are_symmetric() {
+        for topology in [Topology::Square, Topology::Hex, Topology::Triangle] {
+            for x in 0..5 {
+                for y in 0..4 {
+                    for side in 0..topology.sides() {
+                        if let Some((nx, ny)) = topology.neighbor(x, y, side, 5, 4) {
+                            let back = topology.opposite(side);
+                            assert_eq!(topology.neighbor(nx, ny, back, 5, 4), Some((x, y)), "{:?}", topology);
+                            assert_eq!(topology.distance((x, y), (nx, ny)), 1, "{:?}", topology);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_edges_count_neighbor_pairs() {
+        for topology in [Topology::Square, Topology::Hex, Topology::Triangle] {
+            for (width, height) in [(1, 1), (1, 4), (4, 1), (5, 4), (6, 7)] {
+                let pairs = (0..width)
+                    .flat_map(|x| (0..height).map(move |y| (x, y)))
+                    .map(|(x, y)| (0..topology.sides()).filter(|&side| topology.neighbor(x, y, side, width, height).is_some()).count())
+                    .sum::<usize>() / 2;
+                assert_eq!(topology.edges(width, height), Some(pairs), "{:?} {}x{}", topology, width, height);
+            }
+            assert_eq!(topology.edges(usize::MAX, usize::MAX), None);
+            assert_eq!(topology.edges(usize::MAX, 2), None);
+        }
+    }
+
+    #[test]
+    fn test_polygons_share_sides_with_neighbors() {
+        let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+        for topology in [Topology::Square, Topology::Hex, Topology::Triangle] {
+            for x in 0..4 {
+                for y in 0..4 {
+                    let polygon = topology.polygon(x, y);
+                    for side in 0..topology.sides() {
+                        let Some((nx, ny)) = topology.neighbor(x, y, side, 4, 4) else { continue };
+                        let other = topology.polygon(nx, ny);
+                        let back = topology.opposite(side);
+                        let n = topology.sides();
+                        // Both cells draw the shared side between the same two points. Triangles
+                        // pointing down wind the other way, so the order is not checked.
+                        let (a, b) = (polygon[side], polygon[(side + 1) % n]);
+                        let (c, d) = (other[back], other[(back + 1) % n]);
+                        assert!((close(a, c) && close(b, d)) || (close(a, d) && close(b, c)), "{:?}", topology);
+                    }
+                }
+            }
+        }
+    }
+}
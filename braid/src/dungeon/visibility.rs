@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+use crate::dungeon::maze::Maze;
+
+/*
+ * - Visibility: The game rule for what a player standing in a cell can see, applied by the
+ *   dungeon so a view never depends on what a client claims to see.
+ * - Current Cell: Only the cell the player stands in.
+ * - Line of Sight: The cell and every cell down a straight corridor from it, in each direction,
+ *   up to the first wall or locked door.
+ * - Radius: Every cell within the given number of steps through open passages, locked doors
+ *   blocking sight.
+ * - Visible Cells: A mask, indexed [x][y], of the cells visible from a cell.
+ */
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visibility {
+    CurrentCell,
+    #[default]
+    LineOfSight,
+    Radius(usize),
+}
+
+// Check whether a player can see from one cell into an adjacent one.
+fn see_through(maze: &Maze, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> bool {
+    !maze.wall_between(x1, y1, x2, y2) && maze.door_between(x1, y1, x2, y2).is_none()
+}
+
+impl Visibility {
+    // Get the mask of cells a player standing in a cell can see.
+    pub fn visible_cells(&self, maze: &Maze, (x, y): (usize, usize)) -> Vec<Vec<bool>> {
+        let mut visible = vec![vec![false; maze.height]; maze.width];
+        visible[x][y] = true;
+        match *self {
+            Visibility::CurrentCell => {}
+            Visibility::LineOfSight => {
+                for side in 0..maze.topology.sides() {
+                    // Keep crossing the same side. On triangles only the east and west sides
+                    // continue in a straight line, and a line through a base stops when it would
+                    // turn back.
+                    let mut cell = (x, y);
+                    while let Some(next) = maze.topology.neighbor(cell.0, cell.1, side, maze.width, maze.height) {
+                        if !see_through(maze, cell, next) || visible[next.0][next.1] {
+                            break;
+                        }
+                        visible[next.0][next.1] = true;
+                        cell = next;
+                    }
+                }
+            }
+            Visibility::Radius(radius) => {
+                let mut queue = VecDeque::from([((x, y), 0)]);
+                while let Some((cell, distance)) = queue.pop_front() {
+                    if distance == radius {
+                        continue;
+                    }
+                    for next in maze.neighbors(cell.0, cell.1) {
+                        if !visible[next.0][next.1] && see_through(maze, cell, next) {
+                            visible[next.0][next.1] = true;
+                            queue.push_back((next, distance + 1));
+                        }
+                    }
+                }
+            }
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An open 5x1 corridor with a wall between (3,0) and (4,0), and an open 1x3 branch south of (1,0).
+    fn corridor() -> Maze {
+        let mut maze = Maze::new(5, 3);
+        for x in 0..3 {
+            maze.remove_wall(x, 0, x + 1, 0);
+        }
+        maze.remove_wall(1, 0, 1, 1);
+        maze.remove_wall(1, 1, 1, 2);
+        maze
+    }
+
+    fn cells(mask: &[Vec<bool>]) -> Vec<(usize, usize)> {
+        (0..mask.len()).flat_map(|x| (0..mask[x].len()).filter(move |&y| mask[x][y]).map(move |y| (x, y))).collect()
+    }
+
+    #[test]
+    fn test_line_of_sight_follows_corridors() {
+        let maze = corridor();
+        assert_eq!(cells(&Visibility::CurrentCell.visible_cells(&maze, (0, 0))), vec![(0, 0)]);
+        assert_eq!(cells(&Visibility::LineOfSight.visible_cells(&maze, (0, 0))), vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+        // Standing at the junction shows both corridors, but not round the corner.
+        assert_eq!(cells(&Visibility::LineOfSight.visible_cells(&maze, (1, 0))), vec![(0, 0), (1, 0), (1, 1), (1, 2), (2, 0), (3, 0)]);
+        assert_eq!(cells(&Visibility::LineOfSight.visible_cells(&maze, (1, 2))), vec![(1, 0), (1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_radius_counts_steps_and_stops_at_doors() {
+        let mut maze = corridor();
+        assert_eq!(cells(&Visibility::Radius(2).visible_cells(&maze, (0, 0))), vec![(0, 0), (1, 0), (1, 1), (2, 0)]);
+        maze.set_door(1, 0, 2, 0, Some(0));
+        assert_eq!(cells(&Visibility::Radius(2).visible_cells(&maze, (0, 0))), vec![(0, 0), (1, 0), (1, 1)]);
+        assert_eq!(cells(&Visibility::LineOfSight.visible_cells(&maze, (0, 0))), vec![(0, 0), (1, 0)]);
+    }
+}
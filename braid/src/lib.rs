@@ -0,0 +1,13 @@
+/*
+ * - Library: The code shared by the dungeon and player binaries, so both build against the same
+ *   definitions.
+ * - Protocol: The framed wire protocol and every type its messages carry (see protocol).
+ * - Dungeon: Maze generation, solving, views and commitments.
+ * - Player: The player client.
+ * - Blockchain: State channels and settlement.
+ */
+
+pub mod blockchain;
+pub mod dungeon;
+pub mod player;
+pub mod protocol;
@@ -1,171 +1,397 @@
-use std::net::TcpStream;
 use std::io::{Read, Write};
-use serde::{Serialize, Deserialize};
-use std::collections::VecDeque;
-use sha2::{Sha256, Digest}; // For cryptographic commitments.
+use std::net::TcpStream;
+use rand::seq::SliceRandom;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use crate::blockchain::state_channel::StateChannel;
+use crate::dungeon::commitment::{self, Domain};
+use crate::dungeon::render::{self, Overlay};
+use crate::dungeon::topology::MAX_SIDES;
+use crate::protocol::{self, CellProof, Commitment, Direction, Hash, LayeredMaze, Message, Opening, Position};
 
-// Structure to hold player data and their exploration mask for network communication.
-#[derive(Serialize, Deserialize, Clone)]
-struct PlayerData {
-    id: usize,
-    exploration_mask: Vec<Vec<bool>>,
-    commitment: Vec<u8>, // Commitment of the current position.
+// How the game ended, as the player checked it against the dungeon's commitments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOutcome {
+    Finished {
+        treasure: f64,          // Treasure left at the end.
+        solution_cost: usize,   // Turns the revealed solution path takes.
+    },
+    BadSolution,                // The revealed solution path does not open the committed one.
+    BadLayout,                  // The revealed maze does not open the committed layout.
 }
 
 // Structure to represent the player within the client application.
 pub struct Player {
     id: usize,
-    exploration_mask: Vec<Vec<bool>>,
-    commitment: Vec<u8>, // Commitment of the current position.
+    position: Option<(usize, usize, usize)>, // Cell the server last placed the player in.
+    exploration_mask: Vec<Vec<Vec<bool>>>, // Explored cells of every floor, indexed [z][x][y].
+    commitment: Commitment, // Salted commitment of the player's exploration.
+    opening: Option<Opening>, // Opening of the commitment, kept secret until the player reveals their exploration.
+    view: Option<LayeredMaze>, // View of the maze last received from the server.
+    proofs: Vec<Vec<CellProof>>, // Proofs of the cells last shown on each floor, kept to prove what the server revealed.
+    cell_roots: Vec<Hash>, // Roots the server committed every floor's cells to, which every view is checked against.
+    layout_commitment: Commitment, // Commitment to the maze layout, opened when the game ends.
+    solution_commitment: Commitment, // Commitment to the solution path, opened when the game ends.
+    secret_key: SecretKey, // Key the player signs their state channel states with.
+    public_key: PublicKey, // Key the server checks the player's signatures with.
+    channel: StateChannel, // The player's side of their state channel with the server.
+    outcome: Option<GameOutcome>, // How the game ended, once it has.
 }
 
 impl Player {
     // Create a new player with a given ID and maze dimensions.
-    pub fn new(id: usize, maze_width: usize, maze_height: usize) -> Self {
-        let exploration_mask = vec![vec![false; maze_height]; maze_width];
-        let commitment = vec![0; 32]; // Placeholder for the initial commitment.
+    pub fn new(id: usize, maze_width: usize, maze_height: usize, maze_depth: usize) -> Self {
+        let exploration_mask = vec![vec![vec![false; maze_height]; maze_width]; maze_depth];
+        let (secret_key, public_key) = Secp256k1::new().generate_keypair(&mut secp256k1::rand::thread_rng());
         Player {
             id,
+            position: None,
             exploration_mask,
-            commitment,
+            commitment: Commitment::default(), // Nothing is committed to before the first exploration.
+            opening: None,
+            view: None,
+            proofs: vec![],
+            cell_roots: vec![],
+            layout_commitment: Commitment::default(), // Both sent by the server's Welcome.
+            solution_commitment: Commitment::default(),
+            secret_key,
+            public_key,
+            channel: StateChannel::new(&format!("player{}", id), "server"),
+            outcome: None,
         }
     }
 
-    // Connect to the server.
-    pub fn connect(&self, address: &str) -> TcpStream {
-        TcpStream::connect(address).expect("Could not connect to the server")
+    // Connect to the server and introduce the player, receiving the first view of the maze.
+    pub fn connect(&mut self, address: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(address).expect("Could not connect to the server");
+        let hello = Message::Hello {
+            id: self.id,
+            address: self.channel.player_address.clone(),
+            public_key: self.public_key.serialize().to_vec(),
+        };
+        protocol::write_message(&mut stream, &hello).expect("Could not greet the server");
+        self.receive(&mut stream);
+        stream
     }
 
-    // Send exploration data to the server and receive the current view of the maze.
-    pub fn explore(&mut self, stream: &mut TcpStream) {
+    // Send a move to the server and receive the player's position and the current view of the maze.
+    // The server decides where the move leads, so the player's exploration follows its answer.
+    // Returns false once the game is over.
+    pub fn make_move<S: Read + Write>(&mut self, stream: &mut S, direction: Direction) -> bool {
         // Commit the player's current exploration state.
         self.commit_current_state();
 
-        let request = PlayerData {
-            id: self.id,
-            exploration_mask: self.exploration_mask.clone(),
-            commitment: self.commitment.clone(),
+        let request = Message::Move {
+            direction,
+            commitment: self.commitment,
         };
+        if protocol::write_message(stream, &request).is_err() {
+            return false;
+        }
+        self.receive(stream)
+    }
 
-        // Serialize the request to JSON.
-        let request_json = serde_json::to_vec(&request).unwrap();
-        stream.write_all(&request_json).unwrap();
+    // Read the server's messages up to the next view, signing the states it challenges the player
+    // with. A refused move still ends with the view of the round it was refused in, so a refusal
+    // is only reported. Returns false once the game is over, the connection is lost, the server
+    // refuses to let the player join or sends a view its proofs do not hold for.
+    fn receive<S: Read + Write>(&mut self, stream: &mut S) -> bool {
+        loop {
+            match protocol::read_message(stream) {
+                Ok(Message::Welcome { cell_roots, layout_commitment, solution_commitment }) => {
+                    self.cell_roots = cell_roots;
+                    self.layout_commitment = layout_commitment;
+                    self.solution_commitment = solution_commitment;
+                }
+                Ok(Message::View(view)) => {
+                    if !view.verify(&self.cell_roots) || !self.in_view(&view.maze, view.position) {
+                        eprintln!("Refusing a view the committed cell roots do not prove");
+                        return false;
+                    }
+                    // Process the received maze (e.g., display it, update the player's exploration).
+                    let (x, y, z) = view.position;
+                    self.exploration_mask[z][x][y] = true;
+                    self.position = Some(view.position);
+                    println!("Turn {}", view.turn);
+                    self.display_maze(&view.maze);
+                    self.view = Some(view.maze);
+                    self.proofs = view.proofs;
+                    return true;
+                }
+                Ok(Message::Challenge { state }) => {
+                    self.channel.current_state = state.clone();
+                    let signature = self.channel.sign_state(&self.secret_key).serialize_compact().to_vec();
+                    if protocol::write_message(stream, &Message::Sign { state, signature }).is_err() {
+                        return false;
+                    }
+                }
+                Ok(Message::Error { message }) => {
+                    eprintln!("Server refused: {}", message);
+                    // Without a welcome the player never joined, so no view is coming.
+                    if self.cell_roots.is_empty() {
+                        return false;
+                    }
+                }
+                Ok(Message::GameOver { solution_path, opening, solution_cost, maze, layout_opening, treasure }) => {
+                    println!("Game over. Treasure left: {}", treasure);
+                    println!("Solution path: {:?} ({} turns)", solution_path, solution_cost);
+                    self.outcome = Some(if !commitment::verify(Domain::LayeredPath, &self.solution_commitment, &solution_path, &opening) {
+                        GameOutcome::BadSolution
+                    } else if maze.layout_commitment(&layout_opening) != self.layout_commitment {
+                        GameOutcome::BadLayout
+                    } else {
+                        GameOutcome::Finished { treasure, solution_cost }
+                    });
+                    return false;
+                }
+                Ok(_) => eprintln!("Ignoring unexpected message from the server"),
+                Err(e) => {
+                    eprintln!("Connection lost: {}", e);
+                    return false;
+                }
+            }
+        }
+    }
 
-        let mut buffer = [0; 1024];
-        let bytes_read = stream.read(&mut buffer).unwrap();
-        let response: Maze = serde_json::from_slice(&buffer[..bytes_read]).unwrap();
+    // Check that a position lies in the player's maze and on the grid of a view, which no proof
+    // covers.
+    fn in_view(&self, view: &LayeredMaze, (x, y, z): Position) -> bool {
+        let in_mask = self.exploration_mask.get(z).and_then(|floor| floor.get(x)).is_some_and(|column| y < column.len());
+        let on_grid = view.floors.get(z).and_then(|floor| floor.grid.get(x)).is_some_and(|column| y < column.len());
+        in_mask && on_grid
+    }
 
-        // Process the received maze (e.g., display it, update the player's exploration).
-        self.display_maze(&response);
+    // Get how the game ended, once the server has announced it.
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        self.outcome
     }
 
-    // Commit the player's current exploration state using SHA-256.
+    // Commit to the player's current exploration state with a fresh nonce, keeping the opening to
+    // reveal it later.
     fn commit_current_state(&mut self) {
-        let mut hasher = Sha256::new();
-        for (x, row) in self.exploration_mask.iter().enumerate() {
-            for (y, &discovered) in row.iter().enumerate() {
-                if discovered {
-                    hasher.update(format!("{},{}", x, y).as_bytes());
-                }
-            }
+        let (commitment, opening) = commitment::commit(Domain::Exploration, &self.exploration_mask, &mut rand::thread_rng());
+        self.commitment = commitment;
+        self.opening = Some(opening);
+    }
+
+    // Reveal the committed exploration with its opening, for anyone to check with commitment::verify.
+    pub fn reveal_exploration(&self) -> Option<(&[Vec<Vec<bool>>], Opening)> {
+        self.opening.map(|opening| (self.exploration_mask.as_slice(), opening))
+    }
+
+    // Display every floor of the maze in ASCII format, fogging the cells the server did not show.
+    // The server decides what the player can see, so this can be more than they explored.
+    fn display_maze(&self, maze: &LayeredMaze) {
+        for (z, floor) in maze.floors.iter().enumerate() {
+            let visible: Vec<Vec<bool>> = floor.grid.iter()
+                .map(|column| column.iter().map(|cell| cell.visited).collect())
+                .collect();
+            let overlay = Overlay {
+                mask: Some(&visible),
+                position: self.position.filter(|&(_, _, pz)| pz == z).map(|(x, y, _)| (x, y)),
+                stairs: maze.stairs.iter()
+                    .filter(|&&(_, _, sz)| sz == z || sz + 1 == z)
+                    .map(|&(x, y, _)| (x, y))
+                    .collect(),
+                ..Default::default()
+            };
+            println!("Floor {}", z);
+            print!("{}", render::render(floor, &overlay));
         }
-        self.commitment = hasher.finalize().to_vec();
     }
 
-    // Display the maze in ASCII format.
-    fn display_maze(&self, maze: &Maze) {
-        for row in &maze.grid {
-            // Top line of cells.
-            for cell in row {
-                if cell.walls[0] {
-                    print!("+---");  // Print the north wall.
-                } else {
-                    print!("+   ");
-                }
-            }
-            println!("+");
-
-            // Middle line of cells.
-            for cell in row {
-                if cell.walls[3] {
-                    print!("|   ");  // Print the west wall.
-                } else {
-                    print!("    ");
-                }
+    // Simulate player movement and exploration (for demo purposes), walking at random through the
+    // open sides and stairs of the current cell, as last shown by the server.
+    pub fn simulate_exploration<S: Read + Write>(&mut self, stream: &mut S, moves: usize) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..moves {
+            let directions = self.open_directions();
+            let Some(&direction) = directions.choose(&mut rng) else {
+                break;
+            };
+            if !self.make_move(stream, direction) {
+                break;
             }
-            println!("|");
         }
+    }
 
-        // Bottom line of the maze.
-        for _ in 0..maze.width {
-            print!("+---");
+    // Get the directions the last view shows open from the player's cell. Without a view the
+    // player sees nothing, so every side is worth a try.
+    fn open_directions(&self) -> Vec<Direction> {
+        let (Some(view), Some((x, y, z))) = (&self.view, self.position) else {
+            return (0..MAX_SIDES).map(Direction::Side).collect();
+        };
+        let floor = &view.floors[z];
+        let mut directions: Vec<Direction> = (0..floor.topology.sides())
+            .filter(|&side| !floor.grid[x][y].walls[side])
+            .map(Direction::Side)
+            .collect();
+        if z > 0 && view.stairs.contains(&(x, y, z - 1)) {
+            directions.push(Direction::Up);
         }
-        println!("+");
+        if view.stairs.contains(&(x, y, z)) {
+            directions.push(Direction::Down);
+        }
+        directions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::dungeon::merkle::CellTree;
+    use crate::protocol::View;
+
+    // A stream reading a script of the server's messages, and keeping what the player writes.
+    struct Script {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
     }
 
-    // Simulate player movement and exploration (for demo purposes).
-    pub fn simulate_exploration(&mut self) {
-        let mut rng = rand::thread_rng();
-        let mut frontier = VecDeque::new();
-
-        // Start from a random cell.
-        let start_x = rng.gen_range(0..self.exploration_mask.len());
-        let start_y = rng.gen_range(0..self.exploration_mask[0].len());
-        self.exploration_mask[start_x][start_y] = true;
-        frontier.push_back((start_x, start_y));
-
-        while let Some((cx, cy)) = frontier.pop_front() {
-            let neighbors = self.get_unvisited_neighbors(cx, cy);
-            if !neighbors.is_empty() {
-                frontier.push_back((cx, cy));
-                let &(nx, ny) = neighbors.choose(&mut rng).unwrap();
-                self.exploration_mask[nx][ny] = true;
-                frontier.push_back((nx, ny));
+    impl Script {
+        fn new(messages: &[Message]) -> Self {
+            let mut input = vec![];
+            for message in messages {
+                protocol::write_message(&mut input, message).unwrap();
             }
+            Script { input: io::Cursor::new(input), output: vec![] }
+        }
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
 
-    // Get the list of unvisited neighbors of a cell.
-    fn get_unvisited_neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
-        let mut neighbors = vec![];
-        if x > 0 && !self.exploration_mask[x - 1][y] {
-            neighbors.push((x - 1, y));
+    // The dungeon's side of a 6x5 maze of two floors, for scripting what it sends.
+    struct Dungeon {
+        maze: LayeredMaze,
+        trees: Vec<CellTree>,
+        solution_path: Vec<Position>,
+    }
+
+    impl Dungeon {
+        const SOLUTION_OPENING: Opening = Opening { nonce: [4; 32] };
+        const LAYOUT_OPENING: Opening = Opening { nonce: [5; 32] };
+
+        fn new() -> Self {
+            let maze = LayeredMaze::from_seed(6, 5, 2, 11);
+            let trees = maze.floors.iter().map(|floor| CellTree::new(floor, [3; 32])).collect();
+            let solution_path = maze.solution_path(maze.starts[0]).unwrap();
+            Dungeon { maze, trees, solution_path }
         }
-        if x < self.exploration_mask.len() - 1 && !self.exploration_mask[x + 1][y] {
-            neighbors.push((x + 1, y));
+
+        fn welcome(&self) -> Message {
+            Message::Welcome {
+                cell_roots: self.trees.iter().map(CellTree::root).collect(),
+                layout_commitment: self.maze.layout_commitment(&Self::LAYOUT_OPENING),
+                solution_commitment: commitment::commit_with(Domain::LayeredPath, &self.solution_path, &Self::SOLUTION_OPENING),
+            }
         }
-        if y > 0 && !self.exploration_mask[x][y - 1] {
-            neighbors.push((x, y - 1));
+
+        // The end of the game, revealing a solution path and a maze.
+        fn game_over(&self, solution_path: &[Position], maze: &LayeredMaze) -> Message {
+            Message::GameOver {
+                solution_path: solution_path.to_vec(),
+                opening: Self::SOLUTION_OPENING,
+                solution_cost: maze.path_cost(solution_path),
+                maze: maze.clone(),
+                layout_opening: Self::LAYOUT_OPENING,
+                treasure: 12.5,
+            }
         }
-        if y < self.exploration_mask[0].len() - 1 && !self.exploration_mask[x][y + 1] {
-            neighbors.push((x, y + 1));
+
+        // A view showing only the player's cell, if it is in the maze.
+        fn view(&self, (x, y, z): Position, turn: usize) -> Message {
+            let mut mask = vec![vec![vec![false; 5]; 6]; 2];
+            if let Some(cell) = mask.get_mut(z).and_then(|floor| floor.get_mut(x)).and_then(|column| column.get_mut(y)) {
+                *cell = true;
+            }
+            let proofs = self.trees.iter().zip(&self.maze.floors).zip(&mask)
+                .map(|((tree, floor), mask)| tree.prove_mask(floor, mask))
+                .collect();
+            Message::View(View { position: (x, y, z), turn, maze: self.maze.get_masked_maze(&mask), proofs })
         }
-        neighbors
     }
-}
 
-// Structure representing the maze (must match the server-side definition).
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Maze {
-    pub width: usize,
-    pub height: usize,
-    pub grid: Vec<Vec<Cell>>,
-}
+    #[test]
+    fn test_refusals_wait_for_the_rounds_view() {
+        let dungeon = Dungeon::new();
+        let refused = |message: &str| Message::Error { message: message.into() };
+        let mut stream = Script::new(&[
+            dungeon.welcome(),
+            dungeon.view((0, 0, 0), 0),
+            // A move blocked when the round is resolved, then the round's view.
+            refused("cannot move Side(0) from where you are"),
+            dungeon.view((0, 1, 0), 1),
+            // A move refused on arrival, then the view of the round it waited out.
+            refused("player is resting for 1 more rounds"),
+            dungeon.view((1, 1, 0), 2),
+        ]);
+        let mut player = Player::new(1, 6, 5, 2);
+        assert!(player.receive(&mut stream));
+        assert!(player.make_move(&mut stream, Direction::Side(0)));
+        assert_eq!(player.position, Some((0, 1, 0)));
+        assert!(player.make_move(&mut stream, Direction::Side(1)));
+        assert_eq!(player.position, Some((1, 1, 0)));
+        assert!(player.exploration_mask[0][1][1]);
+        assert!(!player.make_move(&mut stream, Direction::Side(2)));
 
-// Structure representing a single cell in the maze (must match the server-side definition).
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Cell {
-    pub x: usize,
-    pub y: usize,
-    pub visited: bool,
-    pub walls: [bool; 4], // Walls of the cell in the order [north, east, south, west].
-}
+        let mut sent = stream.output.as_slice();
+        for _ in 0..3 {
+            assert!(matches!(protocol::read_message(&mut sent).unwrap(), Message::Move { .. }));
+        }
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_refused_hello_stops_the_player() {
+        let mut stream = Script::new(&[Message::Error { message: "player 1 joined with a different key".into() }]);
+        let mut player = Player::new(1, 6, 5, 2);
+        assert!(!player.receive(&mut stream));
+        assert_eq!(player.position, None);
+    }
 
-fn main() {
-    let mut player = Player::new(1, 10, 10); // Create a new player with ID 1 and a 10x10 maze.
-    let mut stream = player.connect("127.0.0.1:7878"); // Connect to the server.
+    #[test]
+    fn test_game_over_is_checked_against_the_commitments() {
+        let dungeon = Dungeon::new();
+        let mut moved = dungeon.maze.clone();
+        moved.treasure = moved.starts[0];
+        let short = &dungeon.solution_path[1..];
+        let cost = dungeon.maze.path_cost(&dungeon.solution_path);
+        for (solution_path, maze, outcome) in [
+            (&dungeon.solution_path[..], &dungeon.maze, GameOutcome::Finished { treasure: 12.5, solution_cost: cost }),
+            (short, &dungeon.maze, GameOutcome::BadSolution),
+            (&dungeon.solution_path[..], &moved, GameOutcome::BadLayout),
+        ] {
+            let mut stream = Script::new(&[dungeon.welcome(), dungeon.view((0, 0, 0), 0), dungeon.game_over(solution_path, maze)]);
+            let mut player = Player::new(1, 6, 5, 2);
+            assert!(player.receive(&mut stream));
+            assert_eq!(player.outcome(), None);
+            assert!(!player.receive(&mut stream));
+            assert_eq!(player.outcome(), Some(outcome));
+        }
+    }
 
-    // Simulate exploration for the demo.
-    player.simulate_exploration();
-    player.explore(&mut stream); // Send exploration data and receive the current maze view.
+    #[test]
+    fn test_views_outside_the_maze_are_refused() {
+        let dungeon = Dungeon::new();
+        for position in [(0, 5, 0), (6, 0, 1), (0, 0, 2)] {
+            let mut stream = Script::new(&[dungeon.welcome(), dungeon.view(position, 0)]);
+            let mut player = Player::new(1, 6, 5, 2);
+            assert!(!player.receive(&mut stream));
+            assert_eq!(player.position, None);
+        }
+    }
 }
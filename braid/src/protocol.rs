@@ -0,0 +1,349 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use serde::{Serialize, Deserialize};
+// Every type sent over the wire, so both binaries name them from here and cannot drift apart.
+pub use crate::blockchain::state_channel::State;
+pub use crate::dungeon::commitment::{Commitment, Opening};
+pub use crate::dungeon::levels::{Direction, LayeredMaze, Position};
+pub use crate::dungeon::maze::{Cell, Feature, Maze, Terrain};
+pub use crate::dungeon::merkle::{CellProof, Hash};
+pub use crate::dungeon::topology::Topology;
+
+/*
+ * Framed wire protocol spoken between the dungeon and players over a stream, shared by both
+ * binaries together with every type a message carries.
+ *
+ * Every message is sent as one frame. All integers are big endian.
+ *
+ *   length          u32       bytes in the rest of the frame, at most MAX_FRAME_LEN
+ *   version         u8        PROTOCOL_VERSION
+ *   message         JSON      a Message
+ *
+ * A frame is read whole before it is decoded, however the stream splits it, so messages of any
+ * size up to MAX_FRAME_LEN arrive intact.
+ *
+ * - Hello: A player joins, or rejoins, the game, with the key they sign states with. The dungeon
+ *   answers with a Welcome, then a View. Rejoining takes the key the player joined with, and a
+ *   connection can only say hello as one player.
+ * - Welcome: What the dungeon committed to before the game: the roots of every floor's cells,
+ *   which every View is checked against, and its layout and solution path, which Game Over opens.
+ * - Move: A player moves one step in the current round. When the round is resolved the dungeon
+ *   sends a Challenge if the move was made, then a View. A player gets one move a round.
+ * - View: What a player can see, sent after their Hello and to every player at the end of each
+ *   round, with proofs for the revealed cells. A player refuses a view whose proofs do not hold.
+ * - Challenge: The dungeon asks a player to sign the state channel state their move led to.
+ * - Sign: A player's signature over a state channel state, answering a Challenge.
+ * - Error: A message was refused. The connection stays open, and a player who has joined still
+ *   gets the View of the round, whether their move was refused on arrival or when the round was
+ *   resolved.
+ * - Game Over: The game has ended. The dungeon reveals its solution path and the whole maze, with
+ *   the openings of their commitments, and closes the connection.
+ */
+
+pub const PROTOCOL_VERSION: u8 = 2;
+pub const MAX_FRAME_LEN: usize = 1 << 24;
+
+// A message of the protocol, in either direction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    Hello {
+        id: usize,              // The player's id.
+        address: String,        // The player's address for their state channel.
+        public_key: Vec<u8>,    // Serialized key the player signs states with.
+    },
+    Move {
+        direction: Direction,   // Direction to move in from the player's current cell.
+        commitment: Commitment, // Salted commitment of the player's exploration.
+    },
+    Welcome {
+        cell_roots: Vec<Hash>,  // Roots of the cell trees, one per floor.
+        layout_commitment: Commitment, // Salted commitment to the maze layout.
+        solution_commitment: Commitment, // Salted commitment to the solution path.
+    },
+    View(View),
+    Challenge {
+        state: State,           // State to sign.
+    },
+    Sign {
+        state: State,           // State signed.
+        signature: Vec<u8>,     // Compact signature over the state.
+    },
+    Error {
+        message: String,        // Why the message was refused.
+    },
+    GameOver {
+        solution_path: Vec<Position>, // The committed solution path.
+        opening: Opening,       // Opening of the solution commitment.
+        solution_cost: usize,   // Turns the solution path takes.
+        maze: LayeredMaze,      // The whole maze, unmasked.
+        layout_opening: Opening, // Opening of the layout commitment.
+        treasure: f64,          // Treasure left at the end.
+    },
+}
+
+// View of the maze sent to a player, with proofs for the revealed cells of each floor.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub position: Position,     // Cell the player is in.
+    pub turn: usize,            // Turns played so far.
+    pub maze: LayeredMaze,      // The masked maze.
+    pub proofs: Vec<Vec<CellProof>>, // Proofs of the revealed cells, per floor.
+}
+
+impl View {
+    // Check the view against the roots of its floors' cell trees: every proof must hold, and every
+    // revealed cell must come with one.
+    pub fn verify(&self, cell_roots: &[Hash]) -> bool {
+        let floors = &self.maze.floors;
+        if cell_roots.len() != floors.len() || self.proofs.len() != floors.len() {
+            return false;
+        }
+        floors.iter().zip(&self.proofs).zip(cell_roots).all(|((floor, proofs), root)| {
+            let revealed = floor.grid.iter().flatten().filter(|cell| cell.visited).count();
+            // Proofs come in [x][y] order, so none can be counted twice.
+            let ordered = proofs.windows(2).all(|pair| (pair[0].x, pair[0].y) < (pair[1].x, pair[1].y));
+            ordered && proofs.len() == revealed && proofs.iter()
+                .all(|proof| proof.verify(floor, root) && floor.grid[proof.x][proof.y].visited)
+        })
+    }
+}
+
+// Error reading or writing a frame.
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    UnsupportedVersion(u8),
+    TooLarge(usize),
+    Malformed(serde_json::Error),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "io error: {}", e),
+            ProtocolError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {}", v),
+            ProtocolError::TooLarge(len) => write!(f, "frame of {} bytes is too large", len),
+            ProtocolError::Malformed(e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::Malformed(e)
+    }
+}
+
+// Encode a message as a whole frame.
+pub fn encode(message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    let body = serde_json::to_vec(message)?;
+    let len = body.len() + 1;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::TooLarge(len));
+    }
+    let mut frame = Vec::with_capacity(4 + len);
+    frame.extend((len as u32).to_be_bytes());
+    frame.push(PROTOCOL_VERSION);
+    frame.extend(body);
+    Ok(frame)
+}
+
+// Decode the rest of a frame, after its length.
+pub fn decode(frame: &[u8]) -> Result<Message, ProtocolError> {
+    match frame.split_first() {
+        Some((&PROTOCOL_VERSION, body)) => Ok(serde_json::from_slice(body)?),
+        Some((&version, _)) => Err(ProtocolError::UnsupportedVersion(version)),
+        None => Err(ProtocolError::Malformed(serde::de::Error::custom("empty frame"))),
+    }
+}
+
+// Write a message as one frame.
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> Result<(), ProtocolError> {
+    writer.write_all(&encode(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Get the length of the rest of a frame from its first four bytes, so a reader knows how much
+// more to read before decoding.
+pub fn frame_len(header: [u8; 4]) -> Result<usize, ProtocolError> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::TooLarge(len));
+    }
+    Ok(len)
+}
+
+// Read one frame and decode its message. A stream closed before a frame starts gives an
+// UnexpectedEof io error.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, ProtocolError> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let mut frame = vec![0; frame_len(header)?];
+    reader.read_exact(&mut frame)?;
+    decode(&frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::merkle::CellTree;
+
+    // A reader handing out at most a few bytes at a time, as TCP may.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_large_views_survive_split_reads() {
+        let maze = LayeredMaze::from_seed(10, 10, 3, 2);
+        let view = View {
+            position: maze.starts[0],
+            turn: 4,
+            maze: maze.get_masked_maze(&vec![vec![vec![true; 10]; 10]; 3]),
+            proofs: vec![vec![]; 3],
+        };
+        let mut stream = vec![];
+        write_message(&mut stream, &Message::View(view)).unwrap();
+        write_message(&mut stream, &Message::Error { message: "blocked".into() }).unwrap();
+        // A whole maze is far more than the 1 KiB a single read used to take.
+        assert!(stream.len() > 1024);
+
+        let mut reader = Trickle(&stream);
+        match read_message(&mut reader).unwrap() {
+            Message::View(view) => {
+                assert_eq!(view.turn, 4);
+                assert_eq!(view.maze.floors[2].grid, maze.floors[2].grid);
+            }
+            _ => panic!("expected a view"),
+        }
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Error { message } if message == "blocked"));
+        assert!(matches!(read_message(&mut reader), Err(ProtocolError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    // One of every message, with a view of a maze holding doors, keys, features and terrain.
+    fn every_message() -> Vec<Message> {
+        let mut maze = LayeredMaze::new(6, 5, 2);
+        for floor in maze.floors.iter_mut() {
+            floor.doors = 1;
+            floor.teleporters = 1;
+            floor.traps = 2;
+            floor.rough = 0.3;
+        }
+        maze.floors[1].topology = Topology::Hex;
+        maze.generate_with_seed(3);
+        let mask = vec![vec![vec![true; 5]; 6]; 2];
+        let proofs = maze.floors.iter()
+            .map(|floor| CellTree::new(floor, [1; 32]).prove_mask(floor, &mask[0]))
+            .collect();
+        let state = State { player_address: "player".into(), move_hash: vec![1, 2, 3], turn_number: 7 };
+        vec![
+            Message::Hello { id: 1, address: "player".into(), public_key: vec![2; 33] },
+            Message::Welcome { cell_roots: vec![[6; 32], [7; 32]], layout_commitment: Commitment([8; 32]), solution_commitment: Commitment([9; 32]) },
+            Message::Move { direction: Direction::Side(2), commitment: Commitment([9; 32]) },
+            Message::Move { direction: Direction::Down, commitment: Commitment::default() },
+            Message::View(View { position: (1, 2, 1), turn: 3, maze: maze.get_masked_maze(&mask), proofs }),
+            Message::Challenge { state: state.clone() },
+            Message::Sign { state, signature: vec![5; 64] },
+            Message::Error { message: "say hello first".into() },
+            Message::GameOver {
+                solution_path: vec![(0, 0, 0), (0, 1, 0)],
+                opening: Opening { nonce: [4; 32] },
+                solution_cost: 2,
+                maze,
+                layout_opening: Opening { nonce: [5; 32] },
+                treasure: 99.5,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_every_message_round_trips() {
+        let messages = every_message();
+        let mut stream = vec![];
+        for message in &messages {
+            write_message(&mut stream, message).unwrap();
+        }
+        let mut reader = stream.as_slice();
+        for message in &messages {
+            assert_eq!(&read_message(&mut reader).unwrap(), message);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_encoding_is_stable() {
+        // A change to the encoding of a message breaks players built against the old one, so it
+        // must come with a new PROTOCOL_VERSION.
+        let frame = encode(&Message::Move { direction: Direction::Up, commitment: Commitment([0; 32]) }).unwrap();
+        let body = format!("{{\"Move\":{{\"direction\":\"Up\",\"commitment\":[{}]}}}}", ["0"; 32].join(","));
+        assert_eq!(&frame[..5], &[0, 0, 0, body.len() as u8 + 1, PROTOCOL_VERSION]);
+        assert_eq!(&frame[5..], body.as_bytes());
+    }
+
+    #[test]
+    fn test_views_are_checked_against_cell_roots() {
+        let maze = LayeredMaze::from_seed(8, 6, 2, 5);
+        let trees: Vec<CellTree> = maze.floors.iter().map(|floor| CellTree::new(floor, [3; 32])).collect();
+        let roots: Vec<Hash> = trees.iter().map(CellTree::root).collect();
+        let mut mask = vec![vec![vec![false; 6]; 8]; 2];
+        for (x, y) in [(0, 0), (0, 1), (3, 2)] {
+            mask[1][x][y] = true;
+        }
+        let proofs = trees.iter().zip(&maze.floors).zip(&mask)
+            .map(|((tree, floor), mask)| tree.prove_mask(floor, mask))
+            .collect();
+        let view = View { position: (0, 0, 1), turn: 1, maze: maze.get_masked_maze(&mask), proofs };
+        assert!(view.verify(&roots));
+        assert!(!view.verify(&roots[..1]));
+        assert!(!view.verify(&[roots[1], roots[0]]));
+
+        // A revealed cell that differs from the committed one, or comes without a proof, fails.
+        let mut altered = view.clone();
+        let side = (0..altered.maze.floors[1].topology.sides()).find(|&side| !altered.maze.floors[1].grid[3][2].walls[side]).unwrap();
+        altered.maze.floors[1].grid[3][2].walls[side] = true;
+        assert!(!altered.verify(&roots));
+        let mut unproven = view.clone();
+        unproven.proofs[1].pop();
+        assert!(!unproven.verify(&roots));
+        let mut repeated = view.clone();
+        repeated.proofs[1][2] = repeated.proofs[1][1].clone();
+        assert!(!repeated.verify(&roots));
+
+        // A grid that does not match the floor's dimensions is refused rather than indexed.
+        let mut misshapen = view;
+        misshapen.maze.floors[1].grid[3].truncate(2);
+        assert!(!misshapen.verify(&roots));
+        misshapen.maze.floors[1].grid.truncate(3);
+        assert!(!misshapen.verify(&roots));
+    }
+
+    #[test]
+    fn test_bad_frames_are_rejected() {
+        let mut frame = encode(&Message::Hello { id: 1, address: "player".into(), public_key: vec![2; 33] }).unwrap();
+        frame[4] = PROTOCOL_VERSION + 1;
+        assert!(matches!(read_message(&mut frame.as_slice()), Err(ProtocolError::UnsupportedVersion(3))));
+
+        let huge = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert!(matches!(read_message(&mut huge.as_slice()), Err(ProtocolError::TooLarge(_))));
+
+        let mut garbage = 4u32.to_be_bytes().to_vec();
+        garbage.extend([PROTOCOL_VERSION, b'{', b'}', b'x']);
+        assert!(matches!(read_message(&mut garbage.as_slice()), Err(ProtocolError::Malformed(_))));
+    }
+}